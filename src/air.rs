@@ -0,0 +1,67 @@
+use crate::field::Field;
+use std::ops::{Add, Mul, Sub};
+
+/// Anything a transition constraint can be evaluated over. The prover evaluates constraints
+/// symbolically over trace polynomials, the verifier evaluates them over field elements at a
+/// query point. Both go through the same `Air::transition_constraints` implementation.
+pub trait ConstraintValue:
+    Clone + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self>
+{
+}
+
+impl<T> ConstraintValue for T where
+    T: Clone + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self>
+{
+}
+
+/// Algebraic intermediate representation of a computation over the field F.
+///
+/// The trace is `trace_width()` columns of `trace_length()` values, each interpolated over the
+/// subgroup of size `trace_length().next_power_of_two()`. Transition constraints read a frame of
+/// `frame_size()` consecutive rows, and must evaluate to zero for every row where the whole
+/// frame fits inside the trace.
+pub trait Air<F: Field> {
+    /// Number of rows in the execution trace
+    fn trace_length(&self) -> usize;
+
+    /// Number of columns in the execution trace
+    fn trace_width(&self) -> usize;
+
+    /// (column, row, value) triples the trace is publicly known to take
+    fn boundary_constraints(&self) -> Vec<(usize, usize, F)>;
+
+    /// Number of consecutive rows read by the transition constraints
+    fn frame_size(&self) -> usize;
+
+    /// Number of polynomials returned by `transition_constraints`
+    fn num_transition_constraints(&self) -> usize;
+
+    /// Highest degree of any transition constraint, in terms of the trace values
+    fn transition_degree(&self) -> usize;
+
+    /// Evaluates each transition constraint over the frame of rows [f(x), f(gx), f(g²x), ...],
+    /// where frame[k][column] is that column's f(g^k x)
+    fn transition_constraints<T: ConstraintValue>(&self, frame: &[Vec<T>]) -> Vec<T>;
+
+    /// Size of the subgroup the trace is interpolated over
+    fn trace_domain_size(&self) -> usize {
+        self.trace_length().next_power_of_two()
+    }
+
+    /// Rows of the trace domain the transition constraints do not hold on
+    fn transition_exemptions(&self) -> std::ops::Range<usize> {
+        self.trace_length() + 1 - self.frame_size()..self.trace_domain_size()
+    }
+
+    /// Power of two strictly greater than the degree of the composition polynomial
+    fn composition_degree_bound(&self) -> usize {
+        let trace_degree = self.trace_length() - 1;
+        let exempt = self.transition_exemptions().len();
+        // Constraints that hold on every row (a row-local one of low degree, say) can have a
+        // quotient of lower degree than the vanishing polynomial, so this stops at zero
+        let transition = (self.transition_degree() * trace_degree + exempt)
+            .saturating_sub(self.trace_domain_size());
+        let boundary = trace_degree - 1;
+        (usize::max(transition, boundary) + 1).next_power_of_two()
+    }
+}
@@ -1,37 +1,126 @@
-use crate::merkle::Hash;
-use crate::proof::Proof;
+use crate::field::Field;
+use crate::hasher::{Sha256, StarkHasher};
 use serde::ser::Serialize;
-use sha2::{Digest, Sha256};
 
-pub struct Channel {
-    state: Hash,
-    data: Vec<u8>,
+/// Fiat-Shamir transcript. The prover and verifier both commit the same data in the same order,
+/// so both derive the same challenges without any interaction.
+#[derive(Clone)]
+pub struct Channel<H: StarkHasher = Sha256> {
+    state: H::Digest,
 }
 
-impl Channel {
+impl<H: StarkHasher> Default for Channel<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: StarkHasher> Channel<H> {
     pub fn new() -> Self {
         Channel {
-            state: [0; 32],
-            data: Vec::new(),
+            state: H::Digest::default(),
         }
     }
 
     pub fn commit(&mut self, data: impl Serialize) {
-        let mut v = bincode::serialize(&data).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(&self.state);
-        hasher.update(&v);
-        self.state = hasher.finalize().into();
-        self.data.append(&mut v);
+        let mut v = self.state.as_ref().to_vec();
+        bincode::serialize_into(&mut v, &data).unwrap();
+        self.state = H::hash(&v);
+    }
+
+    /// Draws n bytes from the state, committing each chunk taken so the next draw differs
+    fn draw_bytes(&mut self, n: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(n);
+        while out.len() < n {
+            let state = H::challenge_bytes(&self.state);
+            let chunk = state[..usize::min(n - out.len(), state.len())].to_vec();
+            self.commit(&chunk);
+            out.extend(chunk);
+        }
+        out
+    }
+
+    /// Draws a uniformly random field element, redrawing whenever the bytes would bias it
+    pub fn draw_field_element<F: Field>(&mut self) -> F {
+        loop {
+            if let Some(f) = F::from_uniform_bytes(&self.draw_bytes(F::SAMPLE_BYTES)) {
+                return f;
+            }
+        }
     }
 
-    pub fn get_u32(&mut self) -> u32 {
-        let f = u32::from_be_bytes([self.state[0], self.state[1], self.state[2], self.state[3]]);
-        self.commit(f);
-        f
+    /// Draws n distinct, uniformly random indices below domain_size, in the order drawn
+    pub fn draw_query_indices(&mut self, n: usize, domain_size: usize) -> Vec<usize> {
+        assert!(
+            n <= domain_size,
+            "can't draw {n} distinct indices below {domain_size}"
+        );
+
+        // Mask to just enough bits to cover the domain, then reject anything past it, or
+        // already drawn
+        let bits = domain_size.next_power_of_two().trailing_zeros();
+        let mut indices = Vec::with_capacity(n);
+        while indices.len() < n {
+            let bytes = self.draw_bytes(8);
+            let x = u64::from_be_bytes(bytes.try_into().unwrap())
+                .checked_shr(64 - bits)
+                .unwrap_or(0) as usize;
+            if x < domain_size && !indices.contains(&x) {
+                indices.push(x);
+            }
+        }
+        indices
     }
+}
 
-    pub fn finalize(self) -> Proof {
-        Proof::new(self.state, self.data.into_boxed_slice())
+#[test]
+fn channel_test() {
+    // Challenges are deterministic from the transcript
+    let mut c0: Channel = Channel::new();
+    let mut c1: Channel = Channel::new();
+    c0.commit(7u32);
+    c1.commit(7u32);
+    let (e0, e1): (crate::F, crate::F) = (c0.draw_field_element(), c1.draw_field_element());
+    assert_eq!(e0, e1);
+    let (e0, e1): (crate::F, crate::F) = (c0.draw_field_element(), c1.draw_field_element());
+    assert_eq!(e0, e1);
+
+    // And depend on everything committed so far
+    let mut c2: Channel = Channel::new();
+    let mut c3: Channel = Channel::new();
+    c2.commit(7u32);
+    c3.commit(8u32);
+    let (e2, e3): (crate::F, crate::F) = (c2.draw_field_element(), c3.draw_field_element());
+    assert_ne!(e2, e3);
+}
+
+#[test]
+fn draw_test() {
+    type G = crate::field::Gf<101>;
+
+    // Field elements are deterministic from the transcript
+    let mut c0: Channel = Channel::new();
+    let mut c1: Channel = Channel::new();
+    c0.commit(7u32);
+    c1.commit(7u32);
+    let e0: crate::E = c0.draw_field_element();
+    assert_eq!(e0, c1.draw_field_element());
+
+    // And cover a small field evenly, 7 bit draws are rejected past 100
+    let mut counts = [0; 101];
+    for _ in 0..10100 {
+        counts[c0.draw_field_element::<G>().residue() as usize] += 1;
     }
+    assert!(counts.iter().all(|&c| (50..150).contains(&c)));
+
+    // Query indices are distinct, in range, and deterministic
+    let indices = Channel::<Sha256>::new().draw_query_indices(40, 48);
+    assert_eq!(indices, Channel::<Sha256>::new().draw_query_indices(40, 48));
+    let mut sorted = indices.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), 40);
+    assert!(sorted.iter().all(|&i| i < 48));
+    assert_eq!(c0.draw_query_indices(8, 8).len(), 8);
+    assert_eq!(c0.draw_query_indices(1, 1), vec![0]);
 }
@@ -0,0 +1,200 @@
+use crate::hasher::StarkHasher;
+use crate::merkle::{self, AuthPath, Leaf, Merkle};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// Commitment to a vector of leaves, any of which can later be opened against the commitment
+/// alone. The prover and verifier only go through this, with `Merkle` as the default.
+pub trait VectorCommitment: Sized {
+    /// What the verifier holds on to, and the transcript commits to
+    type Commitment: Copy + Debug + Eq + Serialize + DeserializeOwned;
+
+    /// Proof of the leaf at one index
+    type Opening: Clone + Debug + PartialEq + Serialize + DeserializeOwned;
+
+    /// Proof of the leaves at several indices at once
+    type BatchOpening: Clone + Debug + PartialEq + Serialize + DeserializeOwned;
+
+    /// Commits to `size` leaves
+    fn commit<T: Leaf>(size: usize, leaves: impl Iterator<Item = T>) -> Self;
+
+    fn commitment(&self) -> Self::Commitment;
+
+    fn open(&self, index: usize) -> Self::Opening;
+
+    /// Opens the leaves at the given indices, sorted and without repeats
+    fn batch_open(&self, indices: &[usize]) -> Self::BatchOpening;
+
+    /// Checks `leaf` is at `index` of the `size` leaves committed to
+    fn verify_opening<T: Leaf>(
+        commitment: &Self::Commitment,
+        size: usize,
+        index: usize,
+        leaf: &T,
+        opening: &Self::Opening,
+    ) -> bool;
+
+    /// Checks every (index, leaf) pair at once, in the order they were opened
+    fn verify_batch_opening<T: Leaf>(
+        commitment: &Self::Commitment,
+        size: usize,
+        elements: &[(usize, T)],
+        opening: &Self::BatchOpening,
+    ) -> bool;
+}
+
+impl<H: StarkHasher> VectorCommitment for Merkle<H> {
+    type Commitment = H::Digest;
+    type Opening = AuthPath<H>;
+    type BatchOpening = AuthPath<H>;
+
+    fn commit<T: Leaf>(size: usize, leaves: impl Iterator<Item = T>) -> Self {
+        Merkle::new(size, leaves)
+    }
+
+    fn commitment(&self) -> Self::Commitment {
+        self.root()
+    }
+
+    fn open(&self, index: usize) -> Self::Opening {
+        self.trace(index)
+    }
+
+    fn batch_open(&self, indices: &[usize]) -> Self::BatchOpening {
+        self.multi_trace(indices)
+    }
+
+    fn verify_opening<T: Leaf>(
+        commitment: &Self::Commitment,
+        size: usize,
+        index: usize,
+        leaf: &T,
+        opening: &Self::Opening,
+    ) -> bool {
+        let row = std::slice::from_ref(leaf);
+        merkle::compute_root_from_path::<H, T>(row, index, size, opening) == Some(*commitment)
+    }
+
+    fn verify_batch_opening<T: Leaf>(
+        commitment: &Self::Commitment,
+        size: usize,
+        elements: &[(usize, T)],
+        opening: &Self::BatchOpening,
+    ) -> bool {
+        merkle::compute_root_from_multi_path::<H, T>(size, elements, opening) == Some(*commitment)
+    }
+}
+
+#[test]
+fn commitment_test() {
+    use crate::channel::Channel;
+    use crate::fibonacci::FibonacciSq;
+    use crate::hasher::Sha256;
+    use crate::proof::VerifyError;
+    use crate::prover::prove;
+    use crate::{E, F};
+
+    // Merkle through the trait is the same as without it
+    let data: Vec<u32> = (0..6).map(|i| i * 3 + 1).collect();
+    let merkle: Merkle = VectorCommitment::commit(6, data.iter().copied());
+    assert_eq!(merkle.commitment(), merkle.root());
+    let commitment = merkle.commitment();
+    let opening = merkle.open(4);
+    assert!(Merkle::<Sha256>::verify_opening(
+        &commitment,
+        6,
+        4,
+        &data[4],
+        &opening
+    ));
+    assert!(!Merkle::<Sha256>::verify_opening(
+        &commitment,
+        6,
+        4,
+        &data[3],
+        &opening
+    ));
+    let opening = merkle.batch_open(&[1, 5]);
+    let mut elements = [(1, data[1]), (5, data[5])];
+    assert!(Merkle::<Sha256>::verify_batch_opening(
+        &commitment,
+        6,
+        &elements,
+        &opening
+    ));
+    elements[1].1 += 1;
+    assert!(!Merkle::<Sha256>::verify_batch_opening(
+        &commitment,
+        6,
+        &elements,
+        &opening
+    ));
+
+    // Any other scheme plugs into the prover, here the hash of every leaf hash, opened by
+    // handing over all of them
+    #[derive(Clone, Debug, PartialEq)]
+    struct Flat(Vec<[u8; 32]>);
+
+    impl Flat {
+        fn hash_leaf(leaf: &impl Leaf) -> [u8; 32] {
+            Sha256::hash(&leaf.to_be_bytes())
+        }
+    }
+
+    impl VectorCommitment for Flat {
+        type Commitment = [u8; 32];
+        type Opening = Vec<[u8; 32]>;
+        type BatchOpening = Vec<[u8; 32]>;
+
+        fn commit<T: Leaf>(size: usize, leaves: impl Iterator<Item = T>) -> Self {
+            let hashes: Vec<[u8; 32]> = leaves.map(|leaf| Self::hash_leaf(&leaf)).collect();
+            assert_eq!(hashes.len(), size);
+            Flat(hashes)
+        }
+
+        fn commitment(&self) -> Self::Commitment {
+            Sha256::hash(&self.0.concat())
+        }
+
+        fn open(&self, _: usize) -> Self::Opening {
+            self.0.clone()
+        }
+
+        fn batch_open(&self, _: &[usize]) -> Self::BatchOpening {
+            self.0.clone()
+        }
+
+        fn verify_opening<T: Leaf>(
+            commitment: &Self::Commitment,
+            size: usize,
+            index: usize,
+            leaf: &T,
+            opening: &Self::Opening,
+        ) -> bool {
+            opening.len() == size
+                && opening.get(index) == Some(&Self::hash_leaf(leaf))
+                && Sha256::hash(&opening.concat()) == *commitment
+        }
+
+        fn verify_batch_opening<T: Leaf>(
+            commitment: &Self::Commitment,
+            size: usize,
+            elements: &[(usize, T)],
+            opening: &Self::BatchOpening,
+        ) -> bool {
+            elements
+                .iter()
+                .all(|(i, leaf)| Self::verify_opening(commitment, size, *i, leaf, opening))
+        }
+    }
+
+    let a = FibonacciSq::trace(31, F::from(3141592));
+    let air = FibonacciSq::new(31, a[30]);
+    let trace = std::slice::from_ref(&a);
+    let proof = prove::<F, E, Sha256, Flat, _>(&air, trace, 4, Channel::new(), true);
+    assert_eq!(proof.verify(&air, 4), Ok(()));
+    let mut bad = proof.clone();
+    bad.trace_decommitment.values[0][0] = F::from(7u32);
+    assert_eq!(bad.verify(&air, 4), Err(VerifyError::TraceMerklePath));
+}
@@ -0,0 +1,244 @@
+use crate::hasher::{Sha256, StarkHasher};
+use crate::merkle::MerkleCap;
+use crate::merkle::{bind_depth, hash_leaves, merge_level, multiproof_nodes, AuthPath, Leaf};
+use memmap2::{Mmap, MmapMut};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Nodes hashed per batch while building, which bounds the memory used on top of the map
+const CHUNK: usize = 1 << 12;
+
+/// A merkle tree laid out like `Merkle`, a heap with the top node first, but kept in a memory
+/// mapped file instead of in memory. Paths are read from the file as they're asked for, apart
+/// from the top levels, which can be kept in RAM as well.
+pub struct DiskMerkle<H: StarkHasher = Sha256> {
+    map: Mmap,
+    size: usize,
+    // Bytes per node in the file, the digest's serialized size
+    node_len: usize,
+    // Copy of the top levels
+    top: Box<[H::Digest]>,
+}
+
+impl<H: StarkHasher> DiskMerkle<H> {
+    /// Builds the tree of `size` leaves into the file at `path`, replacing anything there. Same
+    /// nodes as `Merkle::new`, built a level at a time and a chunk of each level at a time. The
+    /// top `ram_levels` levels are kept in memory.
+    pub fn new<T: Leaf>(
+        path: impl AsRef<Path>,
+        size: usize,
+        mut data: impl Iterator<Item = T>,
+        ram_levels: u32,
+    ) -> io::Result<Self> {
+        assert!(size > 0, "merkle tree without leaves");
+        let padded = size.next_power_of_two();
+        let node_len = node_len::<H>();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(((2 * padded - 1) * node_len) as u64)?;
+        // SAFETY: the file was just created for this tree, nothing else should be changing it
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+
+        // First round of hashing, then the padding
+        let mut offset = padded - 1;
+        loop {
+            let chunk: Vec<T> = data.by_ref().take(CHUNK).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            let hashes = hash_leaves::<H, T>(chunk.into_iter());
+            write_nodes::<H>(&mut map, node_len, offset, &hashes);
+            offset += hashes.len();
+        }
+        assert_eq!(offset, padded - 1 + size, "expected {size} leaves");
+        for i in offset..2 * padded - 1 {
+            write_nodes::<H>(&mut map, node_len, i, &[H::Digest::default()]);
+        }
+
+        // The rest, one level at a time from the level below
+        let mut level = padded - 1;
+        while level > 0 {
+            let parent_level = (level - 1) / 2;
+            let count = level - parent_level;
+            for start in (0..count).step_by(CHUNK) {
+                let n = usize::min(CHUNK, count - start);
+                let children = read_nodes::<H>(&map, node_len, level + 2 * start, 2 * n);
+                let mut parents = vec![H::Digest::default(); n];
+                merge_level::<H>(&mut parents, &children);
+                write_nodes::<H>(&mut map, node_len, parent_level + start, &parents);
+            }
+            level = parent_level;
+        }
+
+        map.flush()?;
+        Ok(Self::with_top(
+            map.make_read_only()?,
+            size,
+            node_len,
+            ram_levels,
+        ))
+    }
+
+    /// Maps a tree of `size` leaves previously built into the file at `path`, which must have
+    /// the given root. The file must not change while it's open. Only the root is checked here,
+    /// a path through a node corrupted further down doesn't verify against it.
+    pub fn open(
+        path: impl AsRef<Path>,
+        size: usize,
+        root: &H::Digest,
+        ram_levels: u32,
+    ) -> io::Result<Self> {
+        let node_len = node_len::<H>();
+        let file = File::open(path)?;
+        let expected = (2 * size.next_power_of_two() - 1) * node_len;
+        if size == 0 || file.metadata()?.len() != expected as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "merkle file doesn't hold a tree of that size",
+            ));
+        }
+        // SAFETY: only ever read, and callers promise not to change the file while it's mapped
+        let map = unsafe { Mmap::map(&file)? };
+        let merkle = Self::with_top(map, size, node_len, ram_levels);
+        if merkle.root() != *root {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "merkle file doesn't match the root",
+            ));
+        }
+        Ok(merkle)
+    }
+
+    fn with_top(map: Mmap, size: usize, node_len: usize, ram_levels: u32) -> Self {
+        let nodes = 2 * size.next_power_of_two() - 1;
+        let cached = 1usize
+            .checked_shl(ram_levels)
+            .map_or(nodes, |n| usize::min(n - 1, nodes));
+        let top = read_nodes::<H>(&map, node_len, 0, cached).into_boxed_slice();
+        Self {
+            map,
+            size,
+            node_len,
+            top,
+        }
+    }
+
+    /// Number of leaves, not counting the padding
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of levels below the top node
+    pub fn depth(&self) -> u32 {
+        self.size.next_power_of_two().trailing_zeros()
+    }
+
+    /// Node at heap index i, from memory if it's in the top levels and the file otherwise
+    pub fn node(&self, i: usize) -> H::Digest {
+        match self.top.get(i) {
+            Some(node) => *node,
+            None => read_nodes::<H>(&self.map, self.node_len, i, 1)[0],
+        }
+    }
+
+    /// Same as `Merkle::root`
+    pub fn root(&self) -> H::Digest {
+        bind_depth::<H>(self.depth(), &self.node(0))
+    }
+
+    /// Same as `Merkle::trace`
+    pub fn trace(&self, i: usize) -> AuthPath<H> {
+        assert!(i < self.size, "no leaf {i}");
+        let mut i = i + (1 << self.depth()) - 1;
+        let mut path = vec![];
+        while i != 0 {
+            path.push(self.node(if i.is_multiple_of(2) { i - 1 } else { i + 1 }));
+            i = (i - 1) / 2;
+        }
+        path.into_boxed_slice()
+    }
+
+    /// Same as `Merkle::multi_trace`
+    pub fn multi_trace(&self, leaves: &[usize]) -> AuthPath<H> {
+        multiproof_nodes(1 << self.depth(), leaves, 0)
+            .into_iter()
+            .map(|i| self.node(i))
+            .collect()
+    }
+
+    /// Same as `Merkle::cap`
+    pub fn cap(&self, height: u32) -> MerkleCap<H> {
+        assert!(height <= self.depth(), "cap above the leaves");
+        ((1 << height) - 1..(2 << height) - 1)
+            .map(|i| self.node(i))
+            .collect()
+    }
+}
+
+/// Bytes a node takes up in the file
+fn node_len<H: StarkHasher>() -> usize {
+    bincode::serialized_size(&H::Digest::default()).unwrap() as usize
+}
+
+fn read_nodes<H: StarkHasher>(map: &[u8], node_len: usize, i: usize, n: usize) -> Vec<H::Digest> {
+    map[i * node_len..(i + n) * node_len]
+        .chunks(node_len)
+        .map(|bytes| bincode::deserialize(bytes).expect("merkle file holds a corrupt node"))
+        .collect()
+}
+
+fn write_nodes<H: StarkHasher>(map: &mut [u8], node_len: usize, i: usize, nodes: &[H::Digest]) {
+    let out = map[i * node_len..(i + nodes.len()) * node_len].chunks_mut(node_len);
+    for (bytes, node) in out.zip(nodes) {
+        bincode::serialize_into(bytes, node).unwrap();
+    }
+}
+
+#[test]
+fn disk_merkle_test() {
+    let path = std::env::temp_dir().join(format!("zkstark-disk-merkle-{}", std::process::id()));
+
+    // Same tree as in memory, across chunk boundaries too, however much of it is cached
+    for (size, ram_levels) in [(1, 0), (5, 2), (16, 64), (5000, 0), (5000, 4)] {
+        let data: Vec<u32> = (0..size as u32).map(|i| i * 7 + 3).collect();
+        let merkle: crate::merkle::Merkle = crate::merkle::Merkle::new(size, data.iter().copied());
+        let disk: DiskMerkle =
+            DiskMerkle::new(&path, size, data.iter().copied(), ram_levels).unwrap();
+
+        assert_eq!(disk.root(), merkle.root());
+        assert_eq!(disk.depth(), merkle.depth());
+        for i in [0, size / 2, size - 1] {
+            assert_eq!(disk.trace(i), merkle.trace(i));
+        }
+        let leaves: Vec<usize> = (0..size).step_by(3).collect();
+        assert_eq!(disk.multi_trace(&leaves), merkle.multi_trace(&leaves));
+        assert_eq!(disk.cap(disk.depth() / 2), merkle.cap(merkle.depth() / 2));
+
+        // And the file opens again as the same tree, but only at its own size and root
+        let root = merkle.root();
+        let reopened: DiskMerkle = DiskMerkle::open(&path, size, &root, 1).unwrap();
+        assert_eq!(reopened.root(), root);
+        assert_eq!(reopened.trace(size - 1), merkle.trace(size - 1));
+        let larger = 2 * size.next_power_of_two();
+        assert!(DiskMerkle::<Sha256>::open(&path, larger, &root, 1).is_err());
+        assert!(DiskMerkle::<Sha256>::open(&path, size, &[0; 32], 1).is_err());
+    }
+
+    // A corrupt top node doesn't open against the root it was built with
+    let data: Vec<u32> = (0..5000).map(|i| i * 7 + 3).collect();
+    let disk: DiskMerkle = DiskMerkle::new(&path, 5000, data.iter().copied(), 0).unwrap();
+    let root = disk.root();
+    drop(disk);
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[0] ^= 1;
+    std::fs::write(&path, bytes).unwrap();
+    assert!(DiskMerkle::<Sha256>::open(&path, 5000, &root, 0).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
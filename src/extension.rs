@@ -0,0 +1,378 @@
+use crate::field::Field;
+use crate::merkle::Leaf;
+use num_traits::pow::Pow;
+use num_traits::{Inv, One, Zero};
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A field containing F, that challenges are drawn from. Elements of F embed into it with `from`.
+pub trait ExtensionOf<F: Field>: Field + From<F> {
+    /// Number of base field coefficients making up an element
+    const DEGREE: usize;
+
+    /// Element with the given coefficients over the base field, lowest power first
+    fn from_base_slice(coeffs: &[F]) -> Self;
+}
+
+// Every field is its own degree 1 extension, for proofs that don't need a bigger one
+impl<F: Field> ExtensionOf<F> for F {
+    const DEGREE: usize = 1;
+
+    fn from_base_slice(coeffs: &[F]) -> Self {
+        assert_eq!(coeffs.len(), 1);
+        coeffs[0]
+    }
+}
+
+/// Element of the degree D extension F[X] / (X^D - W), where W is the generator of F.
+///
+/// A generator isn't a square, cube, or any other q-th power for q dividing P - 1, so the
+/// modulus is irreducible as long as every prime factor of D divides P - 1 (and P = 1 mod 4 if
+/// 4 divides D). Only D = 2 and D = 3 are allowed, anything else fails to compile: 2 divides
+/// every odd P - 1, and 3 divides P - 1 for both fields in this crate. Coefficients are stored
+/// lowest power first.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ExtensionField<F, const D: usize>(pub [F; D]);
+
+impl<F: Field, const D: usize> ExtensionField<F, D> {
+    /// Rejects degrees the modulus isn't known to be irreducible for, so the ring can't have
+    /// zero divisors
+    const IRREDUCIBLE: () = assert!(D == 2 || D == 3, "unsupported extension degree");
+
+    /// The constant term of X^D
+    fn nonresidue() -> F {
+        let () = Self::IRREDUCIBLE;
+        F::generator()
+    }
+
+    pub fn coeffs(&self) -> &[F; D] {
+        &self.0
+    }
+}
+
+impl<F: Field, const D: usize> ExtensionOf<F> for ExtensionField<F, D> {
+    const DEGREE: usize = D;
+
+    fn from_base_slice(coeffs: &[F]) -> Self {
+        let () = Self::IRREDUCIBLE;
+        Self(coeffs.try_into().expect("wrong number of coefficients"))
+    }
+}
+
+impl<F: Field, const D: usize> From<F> for ExtensionField<F, D> {
+    fn from(f: F) -> Self {
+        let mut out = Self::zero();
+        out.0[0] = f;
+        out
+    }
+}
+
+impl<F: Field, const D: usize> From<u32> for ExtensionField<F, D> {
+    fn from(f: u32) -> Self {
+        Self::from(F::from(f))
+    }
+}
+
+impl<F: Field, const D: usize> Pow<u32> for ExtensionField<F, D> {
+    type Output = Self;
+    fn pow(self, mut rhs: u32) -> Self::Output {
+        let (mut base, mut acc) = (self, Self::one());
+        while rhs != 0 {
+            if rhs & 1 == 1 {
+                acc *= base;
+            }
+            base *= base;
+            rhs >>= 1;
+        }
+        acc
+    }
+}
+
+impl<F: Field, const D: usize> Pow<u32> for &ExtensionField<F, D> {
+    type Output = ExtensionField<F, D>;
+    fn pow(self, rhs: u32) -> Self::Output {
+        (*self).pow(rhs)
+    }
+}
+
+impl<F: Field, const D: usize> Field for ExtensionField<F, D> {
+    // Subgroups of F are subgroups of the extension too, which is all the NTT needs
+    const TWO_ADICITY: u32 = F::TWO_ADICITY;
+
+    // Not a generator of the whole extension, but still outside every 2-adic subgroup, which
+    // is all the coset of the evaluation domain needs
+    fn generator() -> Self {
+        Self::from(F::generator())
+    }
+
+    fn root_of_unity(log_n: u32) -> Self {
+        Self::from(F::root_of_unity(log_n))
+    }
+
+    const SAMPLE_BYTES: usize = D * F::SAMPLE_BYTES;
+
+    // Uniform coefficients make a uniform element
+    fn from_uniform_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut out = Self::zero();
+        for (c, chunk) in out.0.iter_mut().zip(bytes.chunks(F::SAMPLE_BYTES)) {
+            *c = F::from_uniform_bytes(chunk)?;
+        }
+        Some(out)
+    }
+}
+
+impl<F: Field, const D: usize> Leaf for ExtensionField<F, D> {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|c| c.to_be_bytes()).collect()
+    }
+}
+
+impl<F: Field, const D: usize> Serialize for ExtensionField<F, D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Fixed size, so no length prefix
+        let mut tuple = serializer.serialize_tuple(D)?;
+        for c in &self.0 {
+            tuple.serialize_element(c)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, F: Field, const D: usize> Deserialize<'de> for ExtensionField<F, D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        struct CoeffVisitor<F, const D: usize>(std::marker::PhantomData<F>);
+
+        impl<'de, F: Field, const D: usize> Visitor<'de> for CoeffVisitor<F, D> {
+            type Value = ExtensionField<F, D>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{D} field elements")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut out = ExtensionField::zero();
+                for (i, c) in out.0.iter_mut().enumerate() {
+                    *c = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+                }
+                Ok(out)
+            }
+        }
+
+        deserializer.deserialize_tuple(D, CoeffVisitor(std::marker::PhantomData))
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Add for ExtensionField<F, D> {
+    type Output = Self;
+    fn add(mut self, rhs: Self) -> Self::Output {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a = *a + b;
+        }
+        self
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Add for &ExtensionField<F, D> {
+    type Output = ExtensionField<F, D>;
+    fn add(self, rhs: Self) -> Self::Output {
+        *self + *rhs
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Sub for ExtensionField<F, D> {
+    type Output = Self;
+    fn sub(mut self, rhs: Self) -> Self::Output {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a = *a - b;
+        }
+        self
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Sub for &ExtensionField<F, D> {
+    type Output = ExtensionField<F, D>;
+    fn sub(self, rhs: Self) -> Self::Output {
+        *self - *rhs
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Mul for ExtensionField<F, D> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        // Schoolbook, folding X^(D + k) back down to W * X^k
+        let w = Self::nonresidue();
+        let mut out = Self::zero();
+        for (i, &a) in self.0.iter().enumerate() {
+            for (j, &b) in rhs.0.iter().enumerate() {
+                if i + j < D {
+                    out.0[i + j] = out.0[i + j] + a * b;
+                } else {
+                    out.0[i + j - D] = out.0[i + j - D] + w * a * b;
+                }
+            }
+        }
+        out
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Mul for &ExtensionField<F, D> {
+    type Output = ExtensionField<F, D>;
+    fn mul(self, rhs: Self) -> Self::Output {
+        *self * *rhs
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::MulAssign for ExtensionField<F, D> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Div for ExtensionField<F, D> {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output {
+        std::ops::Mul::mul(self, rhs.inv())
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Div for &ExtensionField<F, D> {
+    type Output = ExtensionField<F, D>;
+    fn div(self, rhs: Self) -> Self::Output {
+        *self / *rhs
+    }
+}
+
+impl<F: Field, const D: usize> Zero for ExtensionField<F, D> {
+    fn zero() -> Self {
+        let () = Self::IRREDUCIBLE;
+        Self([F::zero(); D])
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(|c| c.is_zero())
+    }
+}
+
+impl<F: Field, const D: usize> One for ExtensionField<F, D> {
+    fn one() -> Self {
+        Self::from(F::one())
+    }
+
+    fn is_one(&self) -> bool {
+        *self == Self::one()
+    }
+}
+
+impl<F: Field, const D: usize> std::ops::Neg for ExtensionField<F, D> {
+    type Output = Self;
+    fn neg(mut self) -> Self::Output {
+        for c in self.0.iter_mut() {
+            *c = -*c;
+        }
+        self
+    }
+}
+
+impl<F: Field, const D: usize> Inv for ExtensionField<F, D> {
+    type Output = Self;
+    fn inv(self) -> Self::Output {
+        // Solve self * b = 1 for b. Column c of the matrix is self * X^c, so row r, column c
+        // holds the X^r coefficient of that product.
+        let w = Self::nonresidue();
+        let mut m = [[F::zero(); D]; D];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, e) in row.iter_mut().enumerate() {
+                *e = if r >= c {
+                    self.0[r - c]
+                } else {
+                    w * self.0[r + D - c]
+                };
+            }
+        }
+        let mut b = Self::one().0;
+
+        // Gauss-Jordan elimination, the matrix is invertible unless self is zero
+        for col in 0..D {
+            let pivot = (col..D)
+                .find(|&r| !m[r][col].is_zero())
+                .expect("zero has no inverse");
+            m.swap(col, pivot);
+            b.swap(col, pivot);
+
+            let scale = m[col][col].inv();
+            for e in m[col].iter_mut() {
+                *e *= scale;
+            }
+            b[col] *= scale;
+
+            let pivot_row = m[col];
+            for r in (0..D).filter(|&r| r != col) {
+                let factor = m[r][col];
+                for (e, p) in m[r].iter_mut().zip(pivot_row) {
+                    *e = *e - factor * p;
+                }
+                b[r] = b[r] - factor * b[col];
+            }
+        }
+
+        Self(b)
+    }
+}
+
+#[test]
+fn extension_test() {
+    use crate::goldilocks::Goldilocks;
+    type F = crate::F;
+
+    // The generator is neither a square nor a cube, so both moduli are irreducible
+    assert_ne!(F::generator().pow(((-F::one()).residue()) / 2), F::one());
+    assert_ne!(F::generator().pow(((-F::one()).residue()) / 3), F::one());
+    assert_ne!(
+        Goldilocks::generator().pow_u64((Goldilocks::ORDER - 1) / 3),
+        Goldilocks::one()
+    );
+
+    fn check<F: Field, const D: usize>(seed: u32) {
+        type E<F, const D: usize> = ExtensionField<F, D>;
+        let elem = |k: u32| {
+            E::<F, D>::from_base_slice(
+                &(0..D as u32)
+                    .map(|i| F::from((k + i).wrapping_mul(2654435761)))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let (a, b, c) = (elem(seed), elem(seed + 7), elem(seed + 13));
+
+        // Field axioms
+        assert_eq!(a * b, b * a);
+        assert_eq!((a * b) * c, a * (b * c));
+        assert_eq!(a * (b + c), a * b + a * c);
+        assert_eq!(a - a, E::zero());
+        assert_eq!(a * a.inv(), E::one());
+        assert_eq!(a / b * b, a);
+        assert_eq!(a.pow(5), a * a * a * a * a);
+
+        // X^D = W
+        let mut x = E::<F, D>::zero();
+        x.0[1] = F::one();
+        assert_eq!(x.pow(D as u32), E::from(F::generator()));
+
+        // The base field embeds, and the 2-adic roots carry over
+        let (f, g) = (F::from(seed), F::from(seed + 1));
+        assert_eq!(E::<F, D>::from(f) * E::from(g), E::from(f * g));
+        assert_eq!(E::<F, D>::root_of_unity(4).pow(16), E::one());
+
+        // Round trips without a length prefix
+        let bytes = bincode::serialize(&a).unwrap();
+        assert_eq!(bytes.len(), bincode::serialize(&a.0[0]).unwrap().len() * D);
+        assert_eq!(bincode::deserialize::<E<F, D>>(&bytes).unwrap(), a);
+    }
+
+    check::<F, 2>(1);
+    check::<F, 3>(2);
+    check::<Goldilocks, 2>(3);
+    check::<Goldilocks, 3>(4);
+}
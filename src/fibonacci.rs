@@ -0,0 +1,115 @@
+use crate::air::{Air, ConstraintValue};
+use crate::field::Field;
+
+/// The stark-101 computation: a[0] = 1, a[1] = secret, a[n + 2] = a[n + 1]² + a[n]²,
+/// with the claim that a[length - 1] = result.
+pub struct FibonacciSq<F: Field = crate::F> {
+    length: usize,
+    result: F,
+}
+
+impl<F: Field> FibonacciSq<F> {
+    pub fn new(length: usize, result: F) -> Self {
+        Self { length, result }
+    }
+
+    /// Generates the trace sequence of `length` elements from the secret
+    pub fn trace(length: usize, secret: F) -> Vec<F> {
+        let mut a = vec![F::one(), secret];
+        for i in 2..length {
+            let t0 = a[i - 2].pow(2);
+            let t1 = a[i - 1].pow(2);
+            a.push(t0 + t1);
+        }
+        a
+    }
+}
+
+impl<F: Field> Air<F> for FibonacciSq<F> {
+    fn trace_length(&self) -> usize {
+        self.length
+    }
+
+    fn trace_width(&self) -> usize {
+        1
+    }
+
+    fn boundary_constraints(&self) -> Vec<(usize, usize, F)> {
+        vec![(0, 0, F::one()), (0, self.length - 1, self.result)]
+    }
+
+    fn frame_size(&self) -> usize {
+        3
+    }
+
+    fn num_transition_constraints(&self) -> usize {
+        1
+    }
+
+    fn transition_degree(&self) -> usize {
+        2
+    }
+
+    // f(g²x) - f(gx)² - f(x)²
+    fn transition_constraints<T: ConstraintValue>(&self, frame: &[Vec<T>]) -> Vec<T> {
+        let (f_x, f_gx, f_ggx) = (
+            frame[0][0].clone(),
+            frame[1][0].clone(),
+            frame[2][0].clone(),
+        );
+        vec![f_ggx - f_gx.clone() * f_gx - f_x.clone() * f_x]
+    }
+}
+
+#[test]
+fn fibonacci_sq_test() {
+    use crate::channel::Channel;
+    use crate::extension::ExtensionField;
+    use crate::hasher::Sha256;
+    use crate::proof::VerifyError;
+    use crate::prover::generate_proof;
+    use crate::{E, F};
+
+    // The stark-101 trace ends in a known value
+    let a = FibonacciSq::trace(1023, F::from(3141592));
+    assert_eq!(a[1022].residue(), 2338775057);
+
+    // Prove and verify a shorter run of the same computation
+    let a = FibonacciSq::trace(31, F::from(3141592));
+    let air = FibonacciSq::new(31, a[30]);
+    let proof =
+        generate_proof::<F, E, Sha256, _>(&air, std::slice::from_ref(&a), 4, Channel::new());
+    assert_eq!(proof.verify(&air, 4), Ok(()));
+
+    // The same proof doesn't prove a different result, or the same one with other parameters.
+    // Challenges are drawn after the statement is committed, so the queries move and the
+    // openings no longer match.
+    assert_eq!(proof.verify(&air, 5), Err(VerifyError::TraceMerklePath));
+    let air = FibonacciSq::new(31, a[29]);
+    assert_eq!(proof.verify(&air, 4), Err(VerifyError::TraceMerklePath));
+
+    // Query counts that can't be drawn are rejected before anything is checked
+    assert_eq!(proof.verify(&air, 0), Err(VerifyError::NoQueries));
+    assert_eq!(proof.verify(&air, 257), Err(VerifyError::MalformedProof));
+
+    // A trace filling its whole subgroup, with challenges from the base field
+    let a = FibonacciSq::trace(32, F::from(3141592));
+    let air = FibonacciSq::new(32, a[31]);
+    let proof =
+        generate_proof::<F, F, Sha256, _>(&air, std::slice::from_ref(&a), 4, Channel::new());
+    assert_eq!(proof.verify(&air, 4), Ok(()));
+
+    // The same computation over the 64 bit field and its quadratic extension
+    type G = crate::goldilocks::Goldilocks;
+    let a = FibonacciSq::trace(32, G::from(3141592));
+    let air = FibonacciSq::new(32, a[31]);
+    let proof = generate_proof::<G, ExtensionField<G, 2>, Sha256, _>(
+        &air,
+        std::slice::from_ref(&a),
+        4,
+        Channel::new(),
+    );
+    assert_eq!(proof.verify(&air, 4), Ok(()));
+    let air = FibonacciSq::new(32, a[30]);
+    assert!(proof.verify(&air, 4).is_err());
+}
@@ -1,8 +1,76 @@
+use crate::merkle::Leaf;
 use num_modular::ModularInteger;
 use num_modular::MontgomeryInt;
 use num_traits::pow::Pow;
-use num_traits::Inv;
-use std::ops::Neg;
+use num_traits::{Inv, One, Zero};
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Debug;
+use std::ops::{Add, Div, Mul, MulAssign, Neg, Sub};
+
+/// A prime field with a large power of two subgroup, everything the polynomial, merkle and
+/// prover code needs from the elements they work over. Elements serialize and hash as their
+/// canonical residue.
+pub trait Field:
+    Copy
+    + Debug
+    + PartialEq
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + MulAssign
+    + Zero
+    + One
+    + Inv<Output = Self>
+    + Pow<u32, Output = Self>
+    + From<u32>
+    + Serialize
+    + DeserializeOwned
+    + Leaf
+{
+    /// Number of factors of two in P - 1, the largest power of two subgroup has 2^TWO_ADICITY
+    /// elements
+    const TWO_ADICITY: u32;
+
+    /// First multiplicative primitive element over the field
+    fn generator() -> Self;
+
+    /// Primitive root of unity of order 2^log_n, generating the subgroup of that size. Panics if
+    /// log_n is larger than TWO_ADICITY.
+    fn root_of_unity(log_n: u32) -> Self;
+
+    /// Number of random bytes `from_uniform_bytes` takes
+    const SAMPLE_BYTES: usize;
+
+    /// Maps SAMPLE_BYTES uniformly random bytes to a uniformly random element, or None if they
+    /// have to be rejected (and redrawn) to keep it unbiased
+    fn from_uniform_bytes(bytes: &[u8]) -> Option<Self>;
+
+    /// Inverts every element in place with Montgomery's trick, a single inversion and about
+    /// 3n multiplications in total. Zero has no inverse and is left as zero, without affecting
+    /// the rest of the slice.
+    fn batch_inverse(values: &mut [Self]) {
+        // prefix[i] is the product of the nonzero elements before i
+        let mut prefix = Vec::with_capacity(values.len());
+        let mut acc = Self::one();
+        for &v in values.iter() {
+            prefix.push(acc);
+            if !v.is_zero() {
+                acc *= v;
+            }
+        }
+
+        // Invert the product of everything, then peel off one element at a time from the back
+        let mut inv = acc.inv();
+        for (v, before) in values.iter_mut().zip(prefix).rev() {
+            if !v.is_zero() {
+                (*v, inv) = (inv * before, inv * *v);
+            }
+        }
+    }
+}
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub struct Gf<const P: u32>(pub MontgomeryInt<u32>);
@@ -10,7 +78,7 @@ pub struct Gf<const P: u32>(pub MontgomeryInt<u32>);
 impl<const P: u32> From<i32> for Gf<P> {
     fn from(f: i32) -> Self {
         Self(if f < 0 {
-            MontgomeryInt::new(f.abs() as u32, &P).neg()
+            MontgomeryInt::new(f.unsigned_abs(), &P).neg()
         } else {
             MontgomeryInt::new(f as u32, &P)
         })
@@ -38,52 +106,116 @@ impl<const P: u32> Pow<u32> for &Gf<P> {
 }
 
 impl<const P: u32> Gf<P> {
+    pub const MODULUS: u32 = P;
+
     pub fn residue(self) -> u32 {
         self.0.residue()
     }
 
     pub fn order(self) -> u32 {
-        (1..)
-            .find_map(|it| (self.pow(it).residue() == 1).then(|| it))
-            .unwrap()
-    }
-
-    // Finds first multiplicative primitive element over F_P
-    pub fn generator() -> Self {
-        // Collect unqiue prime factors
-        let mut prime_factors = vec![];
-        let mut p = P - 1;
-        let mut it = 2;
-        while p != 1 {
-            if p % it == 0 {
-                prime_factors.push(it);
-            }
-            while p % it == 0 {
-                p /= it;
-            }
-            it += 1;
+        (1..).find(|&it| self.pow(it).residue() == 1).unwrap()
+    }
+
+    // Evaluated once per field, at compile time
+    const GENERATOR: u32 = find_generator(P);
+    const TWO_ADIC_ROOT: u32 = pow_mod(Self::GENERATOR, (P - 1) >> (P - 1).trailing_zeros(), P);
+}
+
+impl<const P: u32> Field for Gf<P> {
+    const TWO_ADICITY: u32 = (P - 1).trailing_zeros();
+
+    fn generator() -> Self {
+        Self::from(Self::GENERATOR)
+    }
+
+    fn root_of_unity(log_n: u32) -> Self {
+        assert!(log_n <= Self::TWO_ADICITY, "no subgroup of size 2^{log_n}");
+        Self::from(Self::TWO_ADIC_ROOT).pow(1 << (Self::TWO_ADICITY - log_n))
+    }
+
+    const SAMPLE_BYTES: usize = 4;
+
+    fn from_uniform_bytes(bytes: &[u8]) -> Option<Self> {
+        // Keep just enough bits to cover P, so at most half the draws are rejected
+        let x = u32::from_be_bytes(bytes.try_into().unwrap()) >> P.leading_zeros();
+        (x < P).then(|| Self::from(x))
+    }
+}
+
+impl<const P: u32> Leaf for Gf<P> {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        self.residue().to_be_bytes().to_vec()
+    }
+}
+
+impl<const P: u32> Serialize for Gf<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.residue().serialize(serializer)
+    }
+}
+
+impl<'de, const P: u32> Deserialize<'de> for Gf<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Only accept the canonical residue
+        let residue = u32::deserialize(deserializer)?;
+        if residue >= P {
+            return Err(D::Error::custom("field element out of range"));
+        }
+        Ok(Self::from(residue))
+    }
+}
+
+/// base^exp mod p
+const fn pow_mod(base: u32, mut exp: u32, p: u32) -> u32 {
+    let (mut base, mut acc) = (base as u64, 1u64);
+    while exp != 0 {
+        if exp & 1 == 1 {
+            acc = acc * base % p as u64;
         }
+        base = base * base % p as u64;
+        exp >>= 1;
+    }
+    acc as u32
+}
+
+/// Finds first multiplicative primitive element over F_p
+const fn find_generator(p: u32) -> u32 {
+    // Collect unique prime factors (P - 1 < 2^32 has at most 9 of them)
+    let mut prime_factors = [0; 9];
+    let mut count = 0;
+    let mut rest = p - 1;
+    let mut it = 2;
+    while rest != 1 {
+        // Anything left past sqrt(rest) is prime
+        if (it as u64) * (it as u64) > rest as u64 {
+            prime_factors[count] = rest;
+            count += 1;
+            break;
+        }
+        if rest.is_multiple_of(it) {
+            prime_factors[count] = it;
+            count += 1;
+        }
+        while rest.is_multiple_of(it) {
+            rest /= it;
+        }
+        it += 1;
+    }
 
-        // Convert factors into exponents
-        let exps: Vec<u32> = prime_factors
-            .into_iter()
-            .map(|factor| {
-                (MontgomeryInt::new(P - 1, &P) * MontgomeryInt::new(factor, &P).inv()).residue()
-            })
-            .collect();
-
-        // Test for all x^exp != 1 (mod p) for 2 < x < P, exp <- exps
-        'l1: for x in 2..P {
-            let x = MontgomeryInt::new(x, &P);
-            for exp in &exps {
-                if x.pow(*exp).residue() == 1 {
-                    continue 'l1;
-                }
+    // Test for all x^((p - 1) / factor) != 1 (mod p) for 2 < x < p
+    let mut x = 2;
+    'l1: while x < p {
+        let mut i = 0;
+        while i < count {
+            if pow_mod(x, (p - 1) / prime_factors[i], p) == 1 {
+                x += 1;
+                continue 'l1;
             }
-            return Self(x);
+            i += 1;
         }
-        unreachable!();
+        return x;
     }
+    panic!("no generator");
 }
 
 impl<const P: u32> std::ops::Rem<u32> for Gf<P> {
@@ -176,7 +308,7 @@ impl<const P: u32> std::ops::Div for &Gf<P> {
     }
 }
 
-impl<const P: u32> num_traits::identities::Zero for Gf<P> {
+impl<const P: u32> Zero for Gf<P> {
     fn zero() -> Self {
         Self(MontgomeryInt::new(0, &P))
     }
@@ -186,7 +318,7 @@ impl<const P: u32> num_traits::identities::Zero for Gf<P> {
     }
 }
 
-impl<const P: u32> num_traits::identities::One for Gf<P> {
+impl<const P: u32> One for Gf<P> {
     fn one() -> Self {
         Self(MontgomeryInt::new(1, &P))
     }
@@ -196,7 +328,7 @@ impl<const P: u32> num_traits::identities::One for Gf<P> {
     }
 }
 
-impl<const P: u32> std::ops::Neg for Gf<P> {
+impl<const P: u32> Neg for Gf<P> {
     type Output = Self;
     fn neg(self) -> Self::Output {
         Self(self.0.neg())
@@ -211,6 +343,7 @@ impl<const P: u32> Inv for Gf<P> {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn generator_test() {
     let g = Gf::<4391>::generator();
     assert_eq!(g.order(), 4390);
@@ -224,3 +357,58 @@ fn generator_test() {
     }
     assert_eq!(elems[0], false);
 }
+
+#[test]
+fn root_of_unity_test() {
+    type F = crate::F;
+    assert_eq!(F::TWO_ADICITY, 30);
+    assert_eq!(F::generator().residue(), 5);
+
+    // Roots have the right order, and square down the tower
+    for log_n in [0, 1, 10, 13] {
+        assert_eq!(F::root_of_unity(log_n).order(), 1 << log_n);
+    }
+    assert_eq!(F::root_of_unity(30).pow(1 << 29), -F::from(1));
+    assert_eq!(F::root_of_unity(13).pow(8), F::root_of_unity(10));
+
+    // Generator of a P - 1 with a large prime factor, found at compile time
+    assert_eq!(Gf::<4391>::generator().order(), 4390);
+    assert_eq!(Gf::<4391>::TWO_ADICITY, 1);
+}
+
+#[test]
+fn batch_inverse_test() {
+    use crate::extension::ExtensionField;
+    use crate::goldilocks::Goldilocks;
+
+    fn check<F: Field>(values: Vec<F>) {
+        let mut inverses = values.clone();
+        F::batch_inverse(&mut inverses);
+        for (v, inv) in values.into_iter().zip(inverses) {
+            if v.is_zero() {
+                assert!(inv.is_zero());
+            } else {
+                assert_eq!(inv, v.inv());
+            }
+        }
+    }
+
+    // Zeros anywhere, including the ends, don't affect the rest
+    let values = |zeros: &[u32]| -> Vec<u32> {
+        (0..20)
+            .map(|i| if zeros.contains(&i) { 0 } else { i * i + 3 })
+            .collect()
+    };
+    for zeros in [&[][..], &[0], &[19], &[3, 4, 11]] {
+        check(values(zeros).into_iter().map(crate::F::from).collect());
+        check(values(zeros).into_iter().map(Goldilocks::from).collect());
+        check(
+            values(zeros)
+                .into_iter()
+                .map(ExtensionField::<crate::F, 3>::from)
+                .collect(),
+        );
+    }
+    check::<crate::F>(vec![]);
+    check(vec![crate::F::zero(); 3]);
+}
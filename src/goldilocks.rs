@@ -0,0 +1,320 @@
+use crate::field::Field;
+use crate::merkle::Leaf;
+use num_traits::pow::Pow;
+use num_traits::{Inv, One, Zero};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Element of the Goldilocks field, mod 2^64 - 2^32 + 1. Always stored as the canonical
+/// residue.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Goldilocks(u64);
+
+impl Goldilocks {
+    pub const ORDER: u64 = 0xffff_ffff_0000_0001;
+
+    // 2^64 mod P, the amount to add back on overflow
+    const EPSILON: u64 = 0xffff_ffff;
+
+    pub fn residue(self) -> u64 {
+        self.0
+    }
+
+    pub fn order(self) -> u64 {
+        (1..).find(|&it| self.pow_u64(it).0 == 1).unwrap()
+    }
+
+    pub fn pow_u64(self, mut exp: u64) -> Self {
+        let (mut base, mut acc) = (self, Self::one());
+        while exp != 0 {
+            if exp & 1 == 1 {
+                acc *= base;
+            }
+            base *= base;
+            exp >>= 1;
+        }
+        acc
+    }
+
+    /// Reduces a 128 bit product, using 2^64 = 2^32 - 1 and 2^96 = -1 (mod P)
+    fn reduce128(x: u128) -> Self {
+        let (lo, hi) = (x as u64, (x >> 64) as u64);
+        let (hi_hi, hi_lo) = (hi >> 32, hi & Self::EPSILON);
+
+        // lo - hi_hi * 2^96
+        let (mut t0, borrow) = lo.overflowing_sub(hi_hi);
+        if borrow {
+            t0 = t0.wrapping_sub(Self::EPSILON);
+        }
+
+        // + hi_lo * 2^64
+        let t1 = hi_lo * Self::EPSILON;
+        let (t2, carry) = t0.overflowing_add(t1);
+        Self::from_noncanonical(t2.wrapping_add(Self::EPSILON * carry as u64))
+    }
+
+    fn from_noncanonical(x: u64) -> Self {
+        Self(if x >= Self::ORDER { x - Self::ORDER } else { x })
+    }
+}
+
+impl From<i32> for Goldilocks {
+    fn from(f: i32) -> Self {
+        if f < 0 {
+            -Self(f.unsigned_abs() as u64)
+        } else {
+            Self(f as u64)
+        }
+    }
+}
+
+impl From<u32> for Goldilocks {
+    fn from(f: u32) -> Self {
+        Self(f as u64)
+    }
+}
+
+impl From<u64> for Goldilocks {
+    fn from(f: u64) -> Self {
+        Self::from_noncanonical(f)
+    }
+}
+
+impl Pow<u32> for Goldilocks {
+    type Output = Self;
+    fn pow(self, rhs: u32) -> Self::Output {
+        self.pow_u64(rhs as u64)
+    }
+}
+
+impl Pow<u32> for &Goldilocks {
+    type Output = Goldilocks;
+    fn pow(self, rhs: u32) -> Self::Output {
+        self.pow_u64(rhs as u64)
+    }
+}
+
+impl Field for Goldilocks {
+    const TWO_ADICITY: u32 = 32;
+
+    fn generator() -> Self {
+        Self(7)
+    }
+
+    fn root_of_unity(log_n: u32) -> Self {
+        assert!(log_n <= Self::TWO_ADICITY, "no subgroup of size 2^{log_n}");
+
+        // Root of the whole 2^32 subgroup, squared down to the size asked for
+        let mut root = Self::generator().pow_u64((Self::ORDER - 1) >> Self::TWO_ADICITY);
+        for _ in log_n..Self::TWO_ADICITY {
+            root *= root;
+        }
+        root
+    }
+
+    const SAMPLE_BYTES: usize = 8;
+
+    fn from_uniform_bytes(bytes: &[u8]) -> Option<Self> {
+        let x = u64::from_be_bytes(bytes.try_into().unwrap());
+        (x < Self::ORDER).then_some(Self(x))
+    }
+}
+
+impl Leaf for Goldilocks {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+}
+
+impl Serialize for Goldilocks {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Goldilocks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Only accept the canonical residue
+        let residue = u64::deserialize(deserializer)?;
+        if residue >= Self::ORDER {
+            return Err(D::Error::custom("field element out of range"));
+        }
+        Ok(Self(residue))
+    }
+}
+
+impl std::ops::Add for Goldilocks {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        // Both are below P, so a single wrap around is corrected by adding 2^64 - P
+        let (sum, carry) = self.0.overflowing_add(rhs.0);
+        Self::from_noncanonical(if carry {
+            sum.wrapping_add(Self::EPSILON)
+        } else {
+            sum
+        })
+    }
+}
+
+impl std::ops::Add for &Goldilocks {
+    type Output = Goldilocks;
+    fn add(self, rhs: Self) -> Self::Output {
+        *self + *rhs
+    }
+}
+
+impl std::ops::Sub for Goldilocks {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        // Likewise a wrap below zero is corrected by subtracting 2^64 - P
+        let (diff, borrow) = self.0.overflowing_sub(rhs.0);
+        Self(if borrow {
+            diff.wrapping_sub(Self::EPSILON)
+        } else {
+            diff
+        })
+    }
+}
+
+impl std::ops::Sub<u32> for Goldilocks {
+    type Output = Self;
+    fn sub(self, rhs: u32) -> Self::Output {
+        self - Self::from(rhs)
+    }
+}
+
+impl std::ops::Sub for &Goldilocks {
+    type Output = Goldilocks;
+    fn sub(self, rhs: Self) -> Self::Output {
+        *self - *rhs
+    }
+}
+
+impl std::ops::Mul for Goldilocks {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::reduce128(self.0 as u128 * rhs.0 as u128)
+    }
+}
+
+impl std::ops::Mul<u32> for Goldilocks {
+    type Output = Self;
+    fn mul(self, rhs: u32) -> Self::Output {
+        self * Self::from(rhs)
+    }
+}
+
+impl std::ops::Mul<Goldilocks> for u32 {
+    type Output = Goldilocks;
+    fn mul(self, rhs: Goldilocks) -> Self::Output {
+        Goldilocks::from(self) * rhs
+    }
+}
+
+impl std::ops::Mul for &Goldilocks {
+    type Output = Goldilocks;
+    fn mul(self, rhs: Self) -> Self::Output {
+        *self * *rhs
+    }
+}
+
+impl std::ops::MulAssign for Goldilocks {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl std::ops::Div for Goldilocks {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output {
+        std::ops::Mul::mul(self, rhs.inv())
+    }
+}
+
+impl std::ops::Div for &Goldilocks {
+    type Output = Goldilocks;
+    fn div(self, rhs: Self) -> Self::Output {
+        *self / *rhs
+    }
+}
+
+impl Zero for Goldilocks {
+    fn zero() -> Self {
+        Self(0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl One for Goldilocks {
+    fn one() -> Self {
+        Self(1)
+    }
+
+    fn is_one(&self) -> bool {
+        self.0 == 1
+    }
+}
+
+impl std::ops::Neg for Goldilocks {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self::zero() - self
+    }
+}
+
+impl Inv for Goldilocks {
+    type Output = Self;
+    fn inv(self) -> Self::Output {
+        // Fermat's little theorem, x^(P - 2) = x^-1
+        assert!(!self.is_zero(), "zero has no inverse");
+        self.pow_u64(Self::ORDER - 2)
+    }
+}
+
+#[test]
+fn goldilocks_test() {
+    type G = Goldilocks;
+    let p = G::ORDER as u128;
+
+    // Arithmetic agrees with u128 math, including values around the wrap points
+    let values = [
+        0,
+        1,
+        2,
+        G::EPSILON,
+        G::EPSILON + 1,
+        1 << 63,
+        G::ORDER - 2,
+        G::ORDER - 1,
+        0x1234_5678_9abc_def0,
+    ];
+    for &a in &values {
+        for &b in &values {
+            let (x, y) = (G::from(a), G::from(b));
+            let (a, b) = (a as u128, b as u128);
+            assert_eq!((x + y).residue() as u128, (a + b) % p);
+            assert_eq!((x - y).residue() as u128, (a + p - b) % p);
+            assert_eq!((x * y).residue() as u128, a * b % p);
+            if b != 0 {
+                assert_eq!(x / y * y, x);
+            }
+        }
+    }
+    assert_eq!(G::from(-1), G::from(G::ORDER - 1));
+    assert_eq!(G::from(u64::MAX).residue(), G::EPSILON - 1);
+
+    // Generator isn't in any proper subgroup, P - 1 = 2^32 * 3 * 5 * 17 * 257 * 65537
+    for factor in [2, 3, 5, 17, 257, 65537] {
+        assert_ne!(G::generator().pow_u64((G::ORDER - 1) / factor), G::one());
+    }
+
+    // Roots have the right order, and square down the tower
+    for log_n in [0, 1, 10, 13] {
+        assert_eq!(G::root_of_unity(log_n).order(), 1 << log_n);
+    }
+    assert_eq!(G::root_of_unity(32).pow(1 << 31), -G::one());
+    assert_eq!(G::root_of_unity(13).pow(8), G::root_of_unity(10));
+}
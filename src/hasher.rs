@@ -0,0 +1,160 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::Digest;
+use std::fmt::Debug;
+
+/// Prefix of every merkle leaf's bytes
+pub const LEAF_TAG: u8 = 0;
+
+/// Prefix of the two digests merged into a merkle node
+pub const NODE_TAG: u8 = 1;
+
+/// Prefix of a merkle root, the top node along with the depth of the tree
+pub const ROOT_TAG: u8 = 2;
+
+/// Hash function the merkle trees and the transcript are built on
+pub trait StarkHasher {
+    type Digest: Copy
+        + Debug
+        + Default
+        + Eq
+        + Send
+        + Sync
+        + AsRef<[u8]>
+        + Serialize
+        + DeserializeOwned;
+
+    fn hash(data: &[u8]) -> Self::Digest;
+
+    /// Hash of two digests, the parent of two nodes in a merkle tree. Must be domain separated
+    /// from `hash` of anything starting with LEAF_TAG or ROOT_TAG.
+    fn merge(left: &Self::Digest, right: &Self::Digest) -> Self::Digest;
+
+    /// The uniformly random bytes of a digest, which the transcript draws challenges from. All
+    /// of them for a hash with a byte output.
+    fn challenge_bytes(digest: &Self::Digest) -> Vec<u8> {
+        digest.as_ref().to_vec()
+    }
+}
+
+/// Hashes the concatenation of parts with any RustCrypto hash with a 32 byte output
+fn digest<D: Digest>(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().as_slice().try_into().unwrap()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sha256;
+
+impl StarkHasher for Sha256 {
+    type Digest = [u8; 32];
+
+    fn hash(data: &[u8]) -> Self::Digest {
+        digest::<sha2::Sha256>(&[data])
+    }
+
+    fn merge(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+        digest::<sha2::Sha256>(&[&[NODE_TAG], left, right])
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Blake2s;
+
+impl StarkHasher for Blake2s {
+    type Digest = [u8; 32];
+
+    fn hash(data: &[u8]) -> Self::Digest {
+        digest::<blake2::Blake2s256>(&[data])
+    }
+
+    fn merge(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+        digest::<blake2::Blake2s256>(&[&[NODE_TAG], left, right])
+    }
+}
+
+/// Keccak-256 as used by Ethereum, with the original padding rather than SHA3-256's
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keccak256;
+
+impl StarkHasher for Keccak256 {
+    type Digest = [u8; 32];
+
+    fn hash(data: &[u8]) -> Self::Digest {
+        digest::<sha3::Keccak256>(&[data])
+    }
+
+    fn merge(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+        digest::<sha3::Keccak256>(&[&[NODE_TAG], left, right])
+    }
+}
+
+#[test]
+fn hasher_test() {
+    use crate::channel::Channel;
+    use crate::merkle::{compute_root_from_multi_path, compute_root_from_path, Merkle};
+    use crate::F;
+
+    fn hex(digest: [u8; 32]) -> String {
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    // Known answers for "abc"
+    assert_eq!(
+        hex(Sha256::hash(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        hex(Blake2s::hash(b"abc")),
+        "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"
+    );
+    assert_eq!(
+        hex(Keccak256::hash(b"abc")),
+        "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+    );
+    assert_eq!(
+        Sha256::merge(&[1; 32], &[2; 32]),
+        Sha256::hash(&[&[NODE_TAG][..], &[1; 32], &[2; 32]].concat())
+    );
+
+    // Trees over different hashes have different roots, and only open against their own
+    let sha: Merkle<Sha256> = Merkle::new(4, [1u32, 2, 3, 4].into_iter());
+    let keccak: Merkle<Keccak256> = Merkle::new(4, [1u32, 2, 3, 4].into_iter());
+    assert_ne!(sha.root(), keccak.root());
+    assert_eq!(
+        compute_root_from_path::<Keccak256, _>(&[3u32], 2, 4, &keccak.trace(2)),
+        Some(keccak.root())
+    );
+    assert_ne!(
+        compute_root_from_path::<Sha256, _>(&[3u32], 2, 4, &keccak.trace(2)),
+        Some(keccak.root())
+    );
+
+    // Transcripts over each hash are deterministic, and draw their own challenges
+    fn draw<H: StarkHasher>() -> (F, Vec<usize>) {
+        let mut channel = Channel::<H>::new();
+        channel.commit(7u32);
+        (
+            channel.draw_field_element(),
+            channel.draw_query_indices(4, 64),
+        )
+    }
+    assert_eq!(draw::<Keccak256>(), draw::<Keccak256>());
+    assert_eq!(draw::<Blake2s>(), draw::<Blake2s>());
+    assert_ne!(draw::<Keccak256>().0, draw::<Sha256>().0);
+    assert_ne!(draw::<Blake2s>().0, draw::<Sha256>().0);
+
+    // And multiproofs open trees over each hash
+    fn opens<H: StarkHasher>() -> bool {
+        let data: Vec<u32> = (0..16).map(|i| i * 5 + 2).collect();
+        let merkle: Merkle<H> = Merkle::new(16, data.iter().copied());
+        let elements = [(1, data[1]), (9, data[9]), (10, data[10])];
+        let path = merkle.multi_trace(&[1, 9, 10]);
+        compute_root_from_multi_path::<H, _>(16, &elements, &path) == Some(merkle.root())
+    }
+    assert!(opens::<Keccak256>());
+    assert!(opens::<Blake2s>());
+}
@@ -0,0 +1,24 @@
+pub mod air;
+pub mod channel;
+pub mod commitment;
+#[cfg(feature = "mmap")]
+pub mod disk_merkle;
+pub mod extension;
+pub mod fibonacci;
+pub mod field;
+pub mod goldilocks;
+pub mod hasher;
+pub mod merkle;
+pub mod ntt;
+pub mod polynomial;
+pub mod proof;
+pub mod prover;
+pub mod rescue;
+
+// Represents an element of a prime field
+// All math is done mod 3221225473
+pub type F = field::Gf<3221225473>;
+
+// Challenges are drawn from the cubic extension of F, for ~93 bits of soundness each instead of
+// ~31
+pub type E = extension::ExtensionField<F, 3>;
@@ -1,35 +1,38 @@
-mod channel;
-mod field;
-mod merkle;
-mod polynomial;
-mod proof;
-mod prover;
-
-use channel::Channel;
-use prover::generate_proof;
-
-// Represents an element of a prime field
-// All math is done mod 3221225473
-type F = field::Gf<3221225473>;
+use zkstark::channel::Channel;
+use zkstark::fibonacci::FibonacciSq;
+use zkstark::hasher::Sha256;
+use zkstark::prover::generate_proof;
+use zkstark::{E, F};
 
 fn main() {
     use std::time::Instant;
 
+    // The computation being proven, and the trace generated from the secret (a single column)
+    let trace = vec![FibonacciSq::trace(1023, F::from(3141592))];
+    let air = FibonacciSq::new(1023, F::from(2338775057u32));
+
+    // Number of test points the proof is checked at
+    let num_queries = 16;
+
     // Abstracts the interactive verifier
-    let channel = Channel::new();
+    let channel = Channel::<Sha256>::new();
 
     // Generates a proof, using the channel to provide data
     let start = Instant::now();
-    let proof = generate_proof(channel);
+    let proof = generate_proof::<F, E, _, _>(&air, &trace, num_queries, channel);
     println!("Prover runtime: {:?}", Instant::now().duration_since(start));
 
-    // Verify the proof (this will panic if anything goes wrong, proper error handling comes later)
+    // Verify the proof
     let start = Instant::now();
-    proof.verify();
+    let result = proof.verify(&air, num_queries);
     println!(
         "Verifier runtime: {:?}",
         Instant::now().duration_since(start)
     );
+    if let Err(e) = result {
+        println!("Proof rejected: {e}");
+        return;
+    }
 
     // Yay, we did it. Print proof size.
     println!("Proof size: {:?}", proof.size());
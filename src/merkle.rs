@@ -1,61 +1,137 @@
-use sha2::Digest;
-use sha2::Sha256;
+use crate::hasher::{Sha256, StarkHasher, LEAF_TAG, ROOT_TAG};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read, Write};
 use std::ops::Index;
 
-#[derive(Clone, Debug)]
-pub struct Merkle(Box<[Hash]>);
+/// Magic bytes every saved merkle tree starts with
+pub const MERKLE_MAGIC: [u8; 4] = *b"ZKMT";
+
+/// Version of the saved merkle tree format this build reads and writes
+pub const MERKLE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merkle<H: StarkHasher = Sha256> {
+    // Heap of every node, the top one first and the (padded) leaves last
+    nodes: Box<[H::Digest]>,
+    size: usize,
+}
 
 // [TODO] Implement containers for these some day
-pub type Hash = [u8; 32];
-pub type AuthPath = Box<[Hash]>;
-
-impl Merkle {
-    /// Constructs a pow-of-2 merkle tree as a heap
-    pub fn new(mut size: usize, data: impl Iterator<Item = u32>) -> Self {
-        // Calculate size (ensure size is power of two)
-        let mut i = size;
-        while i != 1 {
-            assert_eq!(i % 2, 0);
-            i >>= 1;
-            size += i;
-        }
+pub type AuthPath<H = Sha256> = Box<[<H as StarkHasher>::Digest]>;
+
+/// The 2^k nodes k levels below the top of a tree, committed in place of the root so paths can
+/// stop short of it
+pub type MerkleCap<H = Sha256> = Box<[<H as StarkHasher>::Digest]>;
+
+/// Anything that can be committed to as a merkle leaf, hashed as its big-endian bytes. A row
+/// of values hashes as all of them back to back, so a row of one hashes as its only value.
+pub trait Leaf {
+    fn to_be_bytes(&self) -> Vec<u8>;
+}
+
+impl<T: Leaf> Leaf for [T] {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        self.iter().flat_map(Leaf::to_be_bytes).collect()
+    }
+}
+
+impl<T: Leaf> Leaf for Vec<T> {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        self.as_slice().to_be_bytes()
+    }
+}
+
+impl<T: Leaf + ?Sized> Leaf for &T {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        (**self).to_be_bytes()
+    }
+}
+
+impl<T: Leaf, const N: usize> Leaf for [T; N] {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        self.as_slice().to_be_bytes()
+    }
+}
+
+/// A leaf of two parts, say a row and its salt, hashes as one after the other
+impl<A: Leaf, B: Leaf> Leaf for (A, B) {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        [self.0.to_be_bytes(), self.1.to_be_bytes()].concat()
+    }
+}
+
+impl Leaf for u8 {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        vec![*self]
+    }
+}
+
+impl Leaf for u32 {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        u32::to_be_bytes(*self).to_vec()
+    }
+}
+
+impl Leaf for u64 {
+    fn to_be_bytes(&self) -> Vec<u8> {
+        u64::to_be_bytes(*self).to_vec()
+    }
+}
+
+impl<H: StarkHasher> Merkle<H> {
+    /// Constructs a merkle tree as a heap. Any number of leaves is fine, the bottom level is
+    /// padded out to a power of two with empty (all zero) digests, which no leaf hashes to.
+    pub fn new<T: Leaf>(size: usize, data: impl Iterator<Item = T>) -> Self {
+        assert!(size > 0, "merkle tree without leaves");
+        let padded = size.next_power_of_two();
 
         // Create output vec
-        let mut out = vec![Hash::default(); size];
-
-        // First round of hashing
-        let mut offset = out.len() / 2;
-        out.splice(
-            offset..,
-            data.map(|v| {
-                let mut hasher = Sha256::new();
-                hasher.update(v.to_be_bytes());
-                hasher.finalize().into()
-            }),
-        );
+        let mut out = vec![H::Digest::default(); 2 * padded - 1];
+
+        // First round of hashing, leaving the padding empty
+        let mut offset = padded - 1;
+        out.splice(offset..offset + size, hash_leaves::<H, T>(data));
+        assert_eq!(out.len(), 2 * padded - 1, "expected {size} leaves");
 
-        // The rest
+        // The rest, one level at a time from the level below
         while offset > 0 {
             offset /= 2;
-            for it in 0..offset + 1 {
-                let index = offset + it;
-                let mut hasher = Sha256::new();
-                hasher.update(out[2 * index + 1]);
-                hasher.update(out[2 * index + 2]);
-                out[index] = hasher.finalize().into();
-            }
+            let (parents, children) = out.split_at_mut(2 * offset + 1);
+            merge_level::<H>(&mut parents[offset..], children);
         }
 
         // Return
-        Self(out.into_boxed_slice())
+        Self {
+            nodes: out.into_boxed_slice(),
+            size,
+        }
+    }
+
+    /// Number of leaves, not counting the padding
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of levels below the top node
+    pub fn depth(&self) -> u32 {
+        (self.nodes.len() / 2 + 1).trailing_zeros()
+    }
+
+    /// The commitment to the tree, its top node bound to its depth. Indexing gives the bare
+    /// nodes, with the top one at 0.
+    pub fn root(&self) -> H::Digest {
+        bind_depth::<H>(self.depth(), &self[0])
     }
 
     /// Generate an authentication path for a leaf node
-    pub fn trace(&self, mut i: usize) -> AuthPath {
-        i += self.0.len() / 2;
+    pub fn trace(&self, mut i: usize) -> AuthPath<H> {
+        i += self.nodes.len() / 2;
         let mut v = vec![];
         while i != 0 {
-            if i % 2 == 0 {
+            if i.is_multiple_of(2) {
                 // Right node, store left
                 v.push(self[i - 1]);
                 i -= 2;
@@ -69,87 +145,386 @@ impl Merkle {
 
         v.into_boxed_slice()
     }
+
+    /// Generate a multiproof for several leaf nodes at once, the fewest nodes needed to get from
+    /// all of them to the root. Nodes shared between paths are only stored once, and nodes the
+    /// verifier can compute from the leaves aren't stored at all. In ascending heap order.
+    pub fn multi_trace(&self, leaves: &[usize]) -> AuthPath<H> {
+        self.multi_trace_to_cap(leaves, 0)
+    }
+
+    /// The cap of the given height, the nodes `height` levels below the top from left to right
+    pub fn cap(&self, height: u32) -> MerkleCap<H> {
+        assert!(height <= self.depth(), "cap above the leaves");
+        self.nodes[(1 << height) - 1..(2 << height) - 1].into()
+    }
+
+    /// Authentication path for a leaf node up to the cap of the given height, the first
+    /// depth - height nodes of `trace`
+    pub fn trace_to_cap(&self, i: usize, height: u32) -> AuthPath<H> {
+        let depth = self.depth();
+        assert!(height <= depth, "cap above the leaves");
+        self.trace(i)[..(depth - height) as usize].into()
+    }
+
+    /// Multiproof for several leaf nodes up to the cap of the given height
+    pub fn multi_trace_to_cap(&self, leaves: &[usize], height: u32) -> AuthPath<H> {
+        assert!(height <= self.depth(), "cap above the leaves");
+        multiproof_nodes(1 << self.depth(), leaves, height)
+            .into_iter()
+            .map(|i| self[i])
+            .collect()
+    }
 }
 
-impl Index<usize> for Merkle {
-    type Output = Hash;
+impl<H: StarkHasher> Merkle<H> {
+    /// Saves the tree along with the values of its leaves, so it can answer queries again after
+    /// a `read`. Little-endian, with lengths as u64:
+    ///
+    /// magic (4 bytes) | version (u32) | size (u64) | root | node count, nodes in heap order |
+    /// value count, values
+    pub fn write<T: Serialize>(&self, values: &[T], mut writer: impl Write) -> io::Result<()> {
+        assert_eq!(values.len(), self.size, "expected {} values", self.size);
+        let header = (MERKLE_MAGIC, MERKLE_VERSION, self.size as u64, self.root());
+        bincode::serialize_into(&mut writer, &header).map_err(|e| into_io(*e))?;
+        bincode::serialize_into(&mut writer, &self.nodes).map_err(|e| into_io(*e))?;
+        bincode::serialize_into(&mut writer, values).map_err(|e| into_io(*e))
+    }
+
+    /// Loads a tree saved with `write`, and the values of its leaves. The tree is rebuilt from
+    /// the values, and every saved node and the saved root have to match it, so a corrupt file
+    /// can't serve wrong decommitments.
+    pub fn read<T: Leaf + DeserializeOwned>(mut reader: impl Read) -> io::Result<(Self, Vec<T>)> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+        // Check the header before trusting the layout of the rest
+        let (magic, version): ([u8; 4], u32) =
+            bincode::deserialize_from(&mut reader).map_err(|e| into_io(*e))?;
+        if magic != MERKLE_MAGIC {
+            return Err(invalid("not a merkle tree"));
+        }
+        if version != MERKLE_VERSION {
+            return Err(invalid("unsupported merkle tree version"));
+        }
+        let (size, root): (u64, H::Digest) =
+            bincode::deserialize_from(&mut reader).map_err(|e| into_io(*e))?;
+
+        // Then the tree, which has to be the one the values build, and come to the root
+        let nodes: Box<[H::Digest]> =
+            bincode::deserialize_from(&mut reader).map_err(|e| into_io(*e))?;
+        let values: Vec<T> = bincode::deserialize_from(&mut reader).map_err(|e| into_io(*e))?;
+        let size = size as usize;
+        if size == 0 || nodes.len() != 2 * size.next_power_of_two() - 1 || values.len() != size {
+            return Err(invalid("merkle tree is malformed"));
+        }
+        let merkle = Self::new(size, values.iter());
+        if merkle.nodes != nodes || merkle.root() != root {
+            return Err(invalid("merkle tree doesn't match its values"));
+        }
+
+        Ok((merkle, values))
+    }
+}
+
+impl<H: StarkHasher> Index<usize> for Merkle<H> {
+    type Output = H::Digest;
     fn index(&self, i: usize) -> &Self::Output {
-        &self.0[i]
+        &self.nodes[i]
+    }
+}
+
+/// Follows an authentication path, starting from a row of elements and it's leaf index, in a
+/// tree with `size` leaves. Returns None if the index is past the last leaf, or the path isn't
+/// as long as the padded tree is deep.
+pub fn compute_root_from_path<H: StarkHasher, T: Leaf>(
+    row: &[T],
+    index: usize,
+    size: usize,
+    path: &AuthPath<H>,
+) -> Option<H::Digest> {
+    let (_, top) = node_from_path::<H, T>(row, index, size, path, 0)?;
+    Some(bind_depth::<H>(
+        size.next_power_of_two().trailing_zeros(),
+        &top,
+    ))
+}
+
+/// Checks an authentication path that stops at a cap, the path from `Merkle::trace_to_cap` of
+/// the cap's height
+pub fn verify_path_to_cap<H: StarkHasher, T: Leaf>(
+    row: &[T],
+    index: usize,
+    size: usize,
+    path: &AuthPath<H>,
+    cap: &MerkleCap<H>,
+) -> bool {
+    if !cap.len().is_power_of_two() {
+        return false;
     }
+    let height = cap.len().trailing_zeros();
+    node_from_path::<H, T>(row, index, size, path, height)
+        .is_some_and(|(i, node)| cap[i + 1 - cap.len()] == node)
 }
 
-/// Follows an authentication path, starting from an element and it's leaf index
-pub fn compute_root_from_path(element: u32, mut index: usize, path: &AuthPath) -> Hash {
-    // Correct index based on path length
-    index += (1 << path.len()) - 1;
+/// Follows a multiproof from several (leaf index, element) pairs, in a tree with `size` leaves.
+/// Returns None if the proof doesn't have exactly the nodes needed.
+pub fn compute_root_from_multi_path<H: StarkHasher, T: Leaf>(
+    size: usize,
+    elements: &[(usize, T)],
+    path: &AuthPath<H>,
+) -> Option<H::Digest> {
+    let nodes = nodes_from_multi_path::<H, T>(size, elements, path, 0)?;
+    let depth = size.next_power_of_two().trailing_zeros();
+    Some(bind_depth::<H>(depth, nodes.get(&0)?))
+}
+
+/// Checks a multiproof that stops at a cap, the proof from `Merkle::multi_trace_to_cap` of the
+/// cap's height
+pub fn verify_multi_path_to_cap<H: StarkHasher, T: Leaf>(
+    size: usize,
+    elements: &[(usize, T)],
+    path: &AuthPath<H>,
+    cap: &MerkleCap<H>,
+) -> bool {
+    if !cap.len().is_power_of_two() {
+        return false;
+    }
+    let height = cap.len().trailing_zeros();
+    nodes_from_multi_path::<H, T>(size, elements, path, height).is_some_and(|nodes| {
+        nodes
+            .iter()
+            .all(|(&i, node)| cap[i + 1 - cap.len()] == *node)
+    })
+}
+
+/// Follows an authentication path from a leaf up to the level `height` below the top, returning
+/// the heap index and hash of the node it reaches. None if the path is the wrong length.
+fn node_from_path<H: StarkHasher, T: Leaf>(
+    row: &[T],
+    index: usize,
+    size: usize,
+    path: &AuthPath<H>,
+    height: u32,
+) -> Option<(usize, H::Digest)> {
+    let padded = size.next_power_of_two();
+    let depth = padded.trailing_zeros();
+    if index >= size || height > depth || path.len() != (depth - height) as usize {
+        return None;
+    }
+
+    // Correct index based on the padded tree size
+    let mut index = index + padded - 1;
 
     // Generate current hash
-    let mut hasher = Sha256::new();
-    hasher.update(element.to_be_bytes());
-    let mut current = hasher.finalize().into();
+    let mut current = hash_leaf::<H>(&row);
 
     // Step through the path
     for hash in path.iter() {
         // If index is a right node
-        let mut hasher = Sha256::new();
-        if index % 2 == 0 {
-            hasher.update(hash);
-            hasher.update(current);
+        if index.is_multiple_of(2) {
+            current = H::merge(hash, &current);
             index -= 2;
         } else {
-            hasher.update(current);
-            hasher.update(hash);
+            current = H::merge(&current, hash);
             index -= 1;
         }
-        current = hasher.finalize().into();
         index >>= 1;
     }
 
-    // Return final hash
-    return current;
+    Some((index, current))
+}
+
+/// Works up from several (leaf index, element) pairs to the level `height` below the top,
+/// returning every node reached there by heap index. None if the proof doesn't have exactly
+/// the nodes needed.
+fn nodes_from_multi_path<H: StarkHasher, T: Leaf>(
+    size: usize,
+    elements: &[(usize, T)],
+    path: &AuthPath<H>,
+    height: u32,
+) -> Option<BTreeMap<usize, H::Digest>> {
+    // Pair the path up with the heap indices it was generated from
+    let leaves: Vec<usize> = elements.iter().map(|&(i, _)| i).collect();
+    let padded = size.next_power_of_two();
+    if height > padded.trailing_zeros() {
+        return None;
+    }
+    if leaves.is_empty() || leaves.iter().any(|&i| i >= size) {
+        return None;
+    }
+    let siblings = multiproof_nodes(padded, &leaves, height);
+    if siblings.len() != path.len() {
+        return None;
+    }
+    let siblings: BTreeMap<usize, H::Digest> =
+        std::iter::zip(siblings, path.iter().copied()).collect();
+
+    // Hash the leaves, rejecting the same leaf claimed twice
+    let mut current = BTreeMap::new();
+    for (i, element) in elements {
+        if current
+            .insert(i + padded - 1, hash_leaf::<H>(element))
+            .is_some()
+        {
+            return None;
+        }
+    }
+
+    // Deepest nodes have the highest heap index, so work back to front, one parent at a time,
+    // until everything left is at the cap. Nodes computed from the leaves take precedence over
+    // the ones stored in the path.
+    let below_cap = (2 << height) - 1;
+    while let Some((i, hash)) = current.pop_last() {
+        if i < below_cap {
+            current.insert(i, hash);
+            return Some(current);
+        }
+        let (left, right) = if i.is_multiple_of(2) {
+            // Right node, left is either computed or stored
+            let left = current
+                .remove(&(i - 1))
+                .or(siblings.get(&(i - 1)).copied())?;
+            (left, hash)
+        } else {
+            // Left node, right must be stored (it would have been popped first otherwise)
+            (hash, *siblings.get(&(i + 1))?)
+        };
+        current.insert((i - 1) / 2, H::merge(&left, &right));
+    }
+
+    None
+}
+
+/// Heap indices of the siblings along the paths of several leaves, up to the cap of the given
+/// height, that aren't on any of the paths themselves. In ascending order, for a tree padded to
+/// `size` leaves.
+pub(crate) fn multiproof_nodes(size: usize, leaves: &[usize], height: u32) -> Vec<usize> {
+    // Every node from the leaves up to (not including) the cap
+    let below_cap = (2 << height) - 1;
+    let mut paths = BTreeSet::new();
+    for &leaf in leaves {
+        let mut i = leaf + size - 1;
+        while i >= below_cap && paths.insert(i) {
+            i = (i - 1) / 2;
+        }
+    }
+
+    // Their siblings, unless those are computed from the leaves anyway
+    let siblings: BTreeSet<usize> = paths
+        .iter()
+        .map(|&i| if i.is_multiple_of(2) { i - 1 } else { i + 1 })
+        .filter(|i| !paths.contains(i))
+        .collect();
+    siblings.into_iter().collect()
+}
+
+/// Passes I/O errors through, anything else bincode rejects is invalid data
+fn into_io(e: bincode::ErrorKind) -> io::Error {
+    match e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Leaves are tagged so no leaf can pass for a node, which are merged with their own tag
+fn hash_leaf<H: StarkHasher>(element: &(impl Leaf + ?Sized)) -> H::Digest {
+    hash_leaf_bytes::<H>(&element.to_be_bytes())
+}
+
+fn hash_leaf_bytes<H: StarkHasher>(bytes: &[u8]) -> H::Digest {
+    H::hash(&[&[LEAF_TAG][..], bytes].concat())
+}
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn hash_leaves<H: StarkHasher, T: Leaf>(
+    data: impl Iterator<Item = T>,
+) -> Vec<H::Digest> {
+    data.map(|element| hash_leaf::<H>(&element)).collect()
+}
+
+/// Hashes the leaves over every core. Leaves themselves needn't be Send, so their bytes are
+/// gathered up front.
+#[cfg(feature = "parallel")]
+pub(crate) fn hash_leaves<H: StarkHasher, T: Leaf>(
+    data: impl Iterator<Item = T>,
+) -> Vec<H::Digest> {
+    let bytes: Vec<Vec<u8>> = data.map(|element| element.to_be_bytes()).collect();
+    bytes
+        .par_iter()
+        .map(|bytes| hash_leaf_bytes::<H>(bytes))
+        .collect()
+}
+
+/// Fills in a level of the tree from the level below it, each parent from the pair of children
+/// under it
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn merge_level<H: StarkHasher>(parents: &mut [H::Digest], children: &[H::Digest]) {
+    for (parent, pair) in parents.iter_mut().zip(children.chunks(2)) {
+        *parent = H::merge(&pair[0], &pair[1]);
+    }
+}
+
+#[cfg(feature = "parallel")]
+pub(crate) fn merge_level<H: StarkHasher>(parents: &mut [H::Digest], children: &[H::Digest]) {
+    parents
+        .par_iter_mut()
+        .zip(children.par_chunks(2))
+        .for_each(|(parent, pair)| *parent = H::merge(&pair[0], &pair[1]));
+}
+
+/// Binds the top node to the depth of the tree, so a path through a tree of another shape
+/// can't reach the same root
+pub(crate) fn bind_depth<H: StarkHasher>(depth: u32, top: &H::Digest) -> H::Digest {
+    H::hash(&[&[ROOT_TAG][..], &depth.to_be_bytes(), top.as_ref()].concat())
 }
 
 #[test]
 fn merkle_test() {
-    let merkle = Merkle::new(4, [0x01, 0x02, 0x03, 0x04].into_iter());
+    let merkle: Merkle = Merkle::new(4, [0x01u32, 0x02, 0x03, 0x04].into_iter());
 
     // Level 1:
     let i3 = [
-        0xb4, 0x07, 0x11, 0xa8, 0x8c, 0x70, 0x39, 0x75, 0x6f, 0xb8, 0xa7, 0x38, 0x27, 0xea, 0xbe,
-        0x2c, 0x0f, 0xe5, 0xa0, 0x34, 0x6c, 0xa7, 0xe0, 0xa1, 0x04, 0xad, 0xc0, 0xfc, 0x76, 0x4f,
-        0x52, 0x8d,
+        0x15, 0xf2, 0xf1, 0xa4, 0x33, 0x9f, 0x5f, 0x2a, 0x31, 0x3b, 0x95, 0x01, 0x5c, 0xad, 0x81,
+        0x24, 0xd0, 0x54, 0xa1, 0x71, 0xac, 0x2f, 0x31, 0xcf, 0x52, 0x9d, 0xda, 0x7c, 0xfb, 0x6a,
+        0x38, 0xb4,
     ];
     let i4 = [
-        0x43, 0x3e, 0xbf, 0x5b, 0xc0, 0x3d, 0xff, 0xa3, 0x85, 0x36, 0x67, 0x32, 0x07, 0xa2, 0x12,
-        0x81, 0x61, 0x2c, 0xef, 0x5f, 0xaa, 0x9b, 0xc7, 0xa4, 0xd5, 0xb9, 0xbe, 0x2f, 0xdb, 0x12,
-        0xcf, 0x1a,
+        0x89, 0xee, 0xfc, 0x18, 0xfa, 0x4b, 0x81, 0x5b, 0xd1, 0xad, 0xed, 0x2f, 0x24, 0xeb, 0x28,
+        0x88, 0x59, 0x93, 0xaa, 0x00, 0xb6, 0xd0, 0x17, 0x1b, 0xf5, 0x00, 0x5f, 0x9d, 0x39, 0xaa,
+        0xea, 0x10,
     ];
     let i5 = [
-        0x88, 0x18, 0x5d, 0x12, 0x8d, 0x99, 0x22, 0xe0, 0xe6, 0xbc, 0xd3, 0x2b, 0x07, 0xb6, 0xc7,
-        0xf2, 0x0f, 0x27, 0x96, 0x8e, 0xab, 0x44, 0x7a, 0x1d, 0x8d, 0x1c, 0xdf, 0x25, 0x0f, 0x79,
-        0xf7, 0xd3,
+        0x01, 0x6a, 0x68, 0x2d, 0x1d, 0xf4, 0xf8, 0x69, 0xb3, 0x2c, 0x48, 0xb0, 0xa9, 0xb4, 0x42,
+        0xa1, 0x49, 0x39, 0x49, 0xfb, 0x85, 0xd9, 0x51, 0xd1, 0x21, 0xc1, 0x14, 0x3b, 0xd3, 0xd5,
+        0xc1, 0xaf,
     ];
     let i6 = [
-        0x1b, 0xc5, 0xd0, 0xe3, 0xdf, 0x0e, 0xa1, 0x2c, 0x4d, 0x00, 0x78, 0x66, 0x8d, 0x14, 0x92,
-        0x4f, 0x95, 0x10, 0x6b, 0xbe, 0x17, 0x3e, 0x19, 0x6d, 0xe5, 0x0f, 0xe1, 0x3a, 0x90, 0x0b,
-        0x09, 0x37,
+        0xb5, 0x90, 0x24, 0x33, 0x31, 0x10, 0xb3, 0x10, 0x86, 0x25, 0xf2, 0x54, 0x47, 0x66, 0x5c,
+        0x1e, 0xbf, 0x10, 0xc6, 0xa6, 0xbb, 0xe9, 0xf0, 0x18, 0xc4, 0x21, 0xf4, 0xb0, 0xdc, 0xb5,
+        0xa9, 0x93,
     ];
     // Level 2:
     let i1 = [
-        0xbe, 0x8d, 0xc3, 0x57, 0xde, 0xcb, 0x6e, 0x09, 0xc8, 0xe5, 0xad, 0x87, 0x4d, 0x3c, 0x4f,
-        0xa7, 0xfc, 0x09, 0x73, 0x0b, 0xbb, 0x5e, 0x90, 0xf4, 0x2c, 0x97, 0xda, 0xd2, 0x0e, 0x00,
-        0x12, 0xd4,
+        0x5e, 0x28, 0xcc, 0x7a, 0x21, 0x51, 0x9b, 0x0b, 0x65, 0x88, 0xc9, 0x41, 0x99, 0x65, 0x1d,
+        0x31, 0x45, 0x88, 0x47, 0x4d, 0x2b, 0xf9, 0xbc, 0xde, 0xef, 0xf8, 0x1e, 0xdf, 0x56, 0xca,
+        0x15, 0xf7,
     ];
     let i2 = [
-        0x6b, 0xed, 0x5b, 0x6d, 0x7a, 0xe0, 0x93, 0xd1, 0x81, 0x2a, 0xb9, 0xbe, 0x5c, 0xbf, 0xa1,
-        0xce, 0x78, 0x78, 0x12, 0xa0, 0x03, 0xd9, 0x5c, 0x11, 0x44, 0x87, 0x20, 0xa4, 0x07, 0xb6,
-        0x17, 0x27,
+        0xe6, 0x95, 0x1f, 0x77, 0xd4, 0xad, 0xc5, 0x67, 0xf8, 0x2e, 0xaa, 0xc6, 0x65, 0xb9, 0xa2,
+        0xd2, 0x03, 0xcc, 0xf4, 0x65, 0x16, 0x11, 0xc6, 0x9e, 0xe2, 0x24, 0x20, 0x26, 0xf2, 0x3b,
+        0xf7, 0xe9,
     ];
     // Level 3:
     let i0 = [
-        0x32, 0x7c, 0xf2, 0x13, 0xe1, 0x73, 0x8d, 0xe4, 0x20, 0x6b, 0xfd, 0x14, 0x29, 0x7c, 0x26,
-        0xc6, 0x82, 0x96, 0x17, 0x50, 0xcb, 0x56, 0x89, 0x7e, 0xd5, 0xe8, 0xf5, 0x19, 0xb0, 0x54,
-        0x8f, 0xf2,
+        0xc7, 0xde, 0x58, 0xba, 0x4f, 0x6e, 0x1a, 0x4f, 0x50, 0x6d, 0xdd, 0x66, 0x71, 0x0f, 0x2b,
+        0x2e, 0xe5, 0x7e, 0x91, 0x58, 0x67, 0x39, 0x1f, 0xc7, 0x6f, 0x96, 0x7c, 0x21, 0xda, 0x35,
+        0xc4, 0x69,
+    ];
+    // Top node bound to the depth:
+    let root = [
+        0xfa, 0xb1, 0x2a, 0x9a, 0x91, 0x84, 0x19, 0x96, 0xa3, 0x67, 0x17, 0xf5, 0x7b, 0xfc, 0x8c,
+        0x75, 0x11, 0xd0, 0x3d, 0x25, 0x04, 0x5b, 0xf9, 0x11, 0xe6, 0x1c, 0x83, 0xa0, 0x74, 0xc5,
+        0x2f, 0xfd,
     ];
 
     // Assert merkle elements
@@ -160,6 +535,7 @@ fn merkle_test() {
     assert_eq!(merkle[4], i4);
     assert_eq!(merkle[5], i5);
     assert_eq!(merkle[6], i6);
+    assert_eq!(merkle.root(), root);
 
     // Trace test on merkle
     let trace0 = merkle.trace(0);
@@ -178,5 +554,281 @@ fn merkle_test() {
     assert_eq!(trace3[1], i1);
 
     // Assert compute
-    assert_eq!(compute_root_from_path(0x01, 0, &trace0), merkle[0]);
+    assert_eq!(
+        compute_root_from_path::<Sha256, _>(&[0x01u32], 0, 4, &trace0),
+        Some(root)
+    );
+
+    // Paths that are too short or too long for the tree are rejected
+    assert_eq!(
+        compute_root_from_path::<Sha256, _>(&[0x01u32], 0, 4, &trace0[..1].into()),
+        None
+    );
+    let long: AuthPath = [&trace0[..], &[i0]].concat().into();
+    assert_eq!(
+        compute_root_from_path::<Sha256, _>(&[0x01u32], 0, 4, &long),
+        None
+    );
+
+    // Even claiming the other size, the root commits to the depth. A node can't pass for a
+    // leaf either, leaves are tagged apart from nodes.
+    assert_ne!(
+        compute_root_from_path::<Sha256, _>(&[0x01u32], 0, 8, &long),
+        Some(root)
+    );
+    assert_ne!(Sha256::hash(&[&[LEAF_TAG][..], &i3, &i4].concat()), i1);
+
+    // Saving and loading gives back the same tree, and the values it was built from
+    let mut bytes = vec![];
+    merkle
+        .write(&[0x01u32, 0x02, 0x03, 0x04], &mut bytes)
+        .unwrap();
+    assert_eq!(bytes.len(), 4 + 4 + 8 + 32 + (8 + 7 * 32) + (8 + 4 * 4));
+    assert_eq!(bytes[..4], MERKLE_MAGIC);
+    assert_eq!(bytes[16..48], root);
+    let (read, values) = Merkle::<Sha256>::read::<u32>(bytes.as_slice()).unwrap();
+    assert_eq!(values, [0x01, 0x02, 0x03, 0x04]);
+    assert_eq!(read.size(), 4);
+    for (i, node) in [i0, i1, i2, i3, i4, i5, i6].into_iter().enumerate() {
+        assert_eq!(read[i], node);
+    }
+    assert_eq!(read.root(), root);
+    assert_eq!(read.trace(2), trace2);
+
+    // Rows of several elements are leaves too, and open as a whole
+    let rows = [vec![1u32, 2], vec![3, 4], vec![5, 6], vec![7, 8]];
+    let merkle: Merkle = Merkle::new(4, rows.iter());
+    assert_eq!(merkle[5], Sha256::hash(&[LEAF_TAG, 0, 0, 0, 5, 0, 0, 0, 6]));
+    assert_eq!(
+        compute_root_from_path::<Sha256, _>(&rows[2], 2, 4, &merkle.trace(2)),
+        Some(merkle.root())
+    );
+    assert_ne!(
+        compute_root_from_path::<Sha256, _>(&[5u32, 7], 2, 4, &merkle.trace(2)),
+        Some(merkle.root())
+    );
+}
+
+#[test]
+fn multi_trace_test() {
+    let data: [u32; 8] = [5, 8, 13, 21, 34, 55, 89, 144];
+    let merkle: Merkle = Merkle::new(8, data.into_iter());
+
+    // Leaves 2 and 3 are siblings, so the proof needs their parent's sibling, then leaf 6's
+    // sibling and its parent's sibling. Everything above is computed from those.
+    let path = merkle.multi_trace(&[2, 3, 6]);
+    assert_eq!(path.len(), 3);
+    assert_eq!(path[..], [merkle[3], merkle[5], merkle[14]]);
+
+    // A single leaf needs its whole path (top down, rather than bottom up), and every leaf
+    // needs nothing
+    let mut single = merkle.trace(4).to_vec();
+    single.reverse();
+    assert_eq!(merkle.multi_trace(&[4]).to_vec(), single);
+    assert!(merkle.multi_trace(&[0, 1, 2, 3, 4, 5, 6, 7]).is_empty());
+
+    // Root is recovered from the leaves, in any order
+    let elements = [(6, 89u32), (2, 13), (3, 21)];
+    assert_eq!(
+        compute_root_from_multi_path::<Sha256, _>(8, &elements, &path),
+        Some(merkle.root())
+    );
+
+    // Wrong element, or missing nodes
+    let elements = [(6, 89u32), (2, 13), (3, 22)];
+    assert_ne!(
+        compute_root_from_multi_path::<Sha256, _>(8, &elements, &path),
+        Some(merkle.root())
+    );
+    let elements = [(2, 13u32), (3, 21)];
+    assert_eq!(
+        compute_root_from_multi_path::<Sha256, _>(8, &elements, &path),
+        None
+    );
+
+    // Every leaf at once, from an empty proof
+    let elements: Vec<(usize, u32)> = data.into_iter().enumerate().collect();
+    assert_eq!(
+        compute_root_from_multi_path::<Sha256, _>(8, &elements, &Box::default()),
+        Some(merkle.root())
+    );
+}
+
+#[test]
+fn cap_test() {
+    let data: Vec<u32> = (0..16).map(|i| i * i).collect();
+    let merkle: Merkle = Merkle::new(16, data.iter().copied());
+
+    // The cap of height 0 is the top node, and the tallest cap is every leaf
+    assert_eq!(merkle.cap(0)[..], [merkle[0]]);
+    assert_eq!(merkle.cap(4)[..], merkle.nodes[15..]);
+
+    for height in 0..=4 {
+        let cap = merkle.cap(height);
+        assert_eq!(cap.len(), 1 << height);
+
+        // Paths stop at the cap, each one height shorter
+        for (i, &element) in data.iter().enumerate() {
+            let path = merkle.trace_to_cap(i, height);
+            assert_eq!(path.len(), 4 - height as usize);
+            assert!(verify_path_to_cap::<Sha256, _>(
+                &[element],
+                i,
+                16,
+                &path,
+                &cap
+            ));
+            assert!(!verify_path_to_cap::<Sha256, _>(
+                &[element + 1],
+                i,
+                16,
+                &path,
+                &cap
+            ));
+        }
+
+        // Multiproofs too, and they share the cap
+        let elements = [(1, data[1]), (2, data[2]), (13, data[13])];
+        let path = merkle.multi_trace_to_cap(&[1, 2, 13], height);
+        assert!(verify_multi_path_to_cap::<Sha256, _>(
+            16, &elements, &path, &cap
+        ));
+        let elements = [(1, data[1]), (2, data[2]), (13, data[12])];
+        assert!(!verify_multi_path_to_cap::<Sha256, _>(
+            16, &elements, &path, &cap
+        ));
+    }
+
+    // A cap of another height needs a path of another length
+    let path = merkle.trace_to_cap(5, 2);
+    assert!(!verify_path_to_cap::<Sha256, _>(
+        &[data[5]],
+        5,
+        16,
+        &path,
+        &merkle.cap(1)
+    ));
+    assert!(!verify_path_to_cap::<Sha256, _>(
+        &[data[5]],
+        5,
+        16,
+        &path,
+        &merkle.cap(3)
+    ));
+
+    // Multiproofs save the nodes above the cap that the leaves don't already cover
+    assert_eq!(merkle.multi_trace(&[0, 5]).len(), 5);
+    assert_eq!(merkle.multi_trace_to_cap(&[0, 5], 2).len(), 4);
+    assert_eq!(merkle.multi_trace(&[0, 5, 10, 15]).len(), 8);
+    assert_eq!(merkle.multi_trace_to_cap(&[0, 5, 10, 15], 2).len(), 8);
+}
+
+#[test]
+fn padding_test() {
+    // Trees of any size open at every leaf, and only there
+    for size in 1..=9 {
+        let data: Vec<u32> = (0..size as u32).map(|i| 3 * i + 1).collect();
+        let merkle: Merkle = Merkle::new(size, data.iter().copied());
+        assert_eq!(merkle.size(), size);
+        assert_eq!(merkle.depth(), size.next_power_of_two().trailing_zeros());
+        for (i, &element) in data.iter().enumerate() {
+            assert_eq!(
+                compute_root_from_path::<Sha256, _>(&[element], i, size, &merkle.trace(i)),
+                Some(merkle.root())
+            );
+        }
+        let padding = size.next_power_of_two() - 1;
+        assert_eq!(
+            compute_root_from_path::<Sha256, _>(&[0u32], size, size, &merkle.trace(padding)),
+            None
+        );
+
+        // All at once too
+        let elements: Vec<(usize, u32)> = data.iter().copied().enumerate().collect();
+        let leaves: Vec<usize> = (0..size).collect();
+        assert_eq!(
+            compute_root_from_multi_path::<Sha256, _>(
+                size,
+                &elements,
+                &merkle.multi_trace(&leaves)
+            ),
+            Some(merkle.root())
+        );
+    }
+
+    // Padding is empty digests, and a padded tree isn't the same as a full one
+    let five: Merkle = Merkle::new(5, [1u32, 2, 3, 4, 5].into_iter());
+    let eight: Merkle = Merkle::new(8, [1u32, 2, 3, 4, 5, 0, 0, 0].into_iter());
+    assert_eq!(five[12], <Sha256 as StarkHasher>::Digest::default());
+    assert_eq!(five.trace(0)[..2], eight.trace(0)[..2]);
+    assert_ne!(five.trace(0)[2], eight.trace(0)[2]);
+    assert_ne!(five.root(), eight.root());
+}
+
+#[test]
+fn construction_test() {
+    // Every node is its children merged, and every leaf its element hashed, however the tree
+    // was built (this runs with and without the parallel feature)
+    let data: Vec<u64> = (0..1000).map(|i| i * 0x9e37_79b9).collect();
+    let merkle: Merkle = Merkle::new(1000, data.iter().copied());
+    for i in 0..1023 {
+        assert_eq!(
+            merkle[i],
+            Sha256::merge(&merkle[2 * i + 1], &merkle[2 * i + 2])
+        );
+    }
+    for (i, element) in data.iter().enumerate() {
+        assert_eq!(merkle[1023 + i], hash_leaf::<Sha256>(element));
+    }
+    assert!(merkle.nodes[2023..].iter().all(|node| *node == [0; 32]));
+}
+
+#[test]
+fn merkle_file_test() {
+    let data: Vec<u64> = (0..5).map(|i| i * i).collect();
+    let merkle: Merkle = Merkle::new(5, data.iter());
+    let mut bytes = vec![];
+    merkle.write(&data, &mut bytes).unwrap();
+
+    // Padded trees round trip too
+    let (read, values) = Merkle::<Sha256>::read::<u64>(bytes.as_slice()).unwrap();
+    assert_eq!(values, data);
+    assert_eq!(read.root(), merkle.root());
+    assert_eq!(read.multi_trace(&[1, 4]), merkle.multi_trace(&[1, 4]));
+
+    // Not a tree, an unknown version, cut short, or not matching the root
+    let read = |bytes: &[u8]| {
+        Merkle::<Sha256>::read::<u64>(bytes)
+            .map(|_| ())
+            .unwrap_err()
+    };
+    let mut other = bytes.clone();
+    other[0] = b'X';
+    assert_eq!(read(&other).kind(), io::ErrorKind::InvalidData);
+    let mut other = bytes.clone();
+    other[4] = 2;
+    assert_eq!(read(&other).kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        read(&bytes[..bytes.len() - 1]).kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+    let mut other = bytes.clone();
+    other[16] ^= 1;
+    assert_eq!(read(&other).kind(), io::ErrorKind::InvalidData);
+    let mut other = bytes.clone();
+    other[56] ^= 1;
+    assert_eq!(read(&other).kind(), io::ErrorKind::InvalidData);
+
+    // Nor any other node, or value. Nodes start at byte 56, values at 544.
+    for byte in [
+        56 + 32,
+        56 + 7 * 32,
+        56 + 14 * 32 + 31,
+        544,
+        bytes.len() - 8,
+    ] {
+        let mut other = bytes.clone();
+        other[byte] ^= 1;
+        assert_eq!(read(&other).kind(), io::ErrorKind::InvalidData);
+    }
 }
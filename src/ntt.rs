@@ -0,0 +1,172 @@
+use crate::field::Field;
+use crate::polynomial::{schoolbook, Convolve, Polynomial};
+use std::ops::{Add, Mul};
+
+/// Products with fewer coefficients than this on either side use schoolbook multiplication
+const NTT_THRESHOLD: usize = 32;
+
+/// Number theoretic transform, evaluates the polynomial with coefficients `a` over the powers
+/// of `root`, in place. `root` must have order a.len(), which must be a power of two.
+pub fn ntt<F: Field>(a: &mut [F], root: F) {
+    let n = a.len();
+    assert!(n.is_power_of_two());
+    if n == 1 {
+        return;
+    }
+
+    // Reorder into bit reversed order
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            a.swap(i, j);
+        }
+    }
+
+    // Iterative Cooley-Tukey butterflies, doubling the transform size each round
+    let mut len = 2;
+    while len <= n {
+        let w = root.pow((n / len) as u32);
+        for chunk in a.chunks_mut(len) {
+            let mut wk = F::one();
+            for k in 0..len / 2 {
+                let u = chunk[k];
+                let v = chunk[k + len / 2] * wk;
+                chunk[k] = u + v;
+                chunk[k + len / 2] = u - v;
+                wk *= w;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Inverse of `ntt`, interpolates the coefficients from evaluations over the powers of `root`
+pub fn intt<F: Field>(a: &mut [F], root: F) {
+    ntt(a, root.inv());
+    let n_inv = F::from(a.len() as u32).inv();
+    for e in a.iter_mut() {
+        *e *= n_inv;
+    }
+}
+
+/// Interpolates the polynomial through (root^i, values[i]), where root generates a subgroup
+/// of exactly values.len() elements
+pub fn interpolate_subgroup<F: Field>(values: &[F], root: F) -> Polynomial<F> {
+    let mut coeffs = values.to_vec();
+    intt(&mut coeffs, root);
+    Polynomial::from_coeffs(coeffs)
+}
+
+impl<F: Field> Polynomial<F> {
+    /// Evaluates the polynomial over the coset offset * <w>, where w generates the subgroup of
+    /// `size` elements. Output is in the order offset * w^i.
+    pub fn evaluate_over_coset(&self, offset: F, size: usize) -> Vec<F> {
+        let root = F::root_of_unity(size.trailing_zeros());
+
+        // p(offset * x) has coefficients c_i * offset^i. Coefficients past `size` wrap around,
+        // as w^(i + size) == w^i.
+        let mut evals = vec![F::zero(); size];
+        let mut scale = F::one();
+        for (i, &coeff) in self.coeffs().iter().enumerate() {
+            evals[i % size] = evals[i % size] + coeff * scale;
+            scale *= offset;
+        }
+
+        ntt(&mut evals, root);
+        evals
+    }
+}
+
+/// Multiplies two coefficient vectors by evaluating both over a large enough subgroup,
+/// multiplying pointwise, and interpolating back
+fn ntt_mul<F: Field>(a: &[F], b: &[F], root: F, n: usize) -> Vec<F> {
+    let mut fa = a.to_vec();
+    let mut fb = b.to_vec();
+    fa.resize(n, F::zero());
+    fb.resize(n, F::zero());
+    ntt(&mut fa, root);
+    ntt(&mut fb, root);
+    for (x, y) in fa.iter_mut().zip(fb) {
+        *x *= y;
+    }
+    intt(&mut fa, root);
+    fa.truncate(a.len() + b.len() - 1);
+    fa
+}
+
+impl<F: Field> Convolve for F
+where
+    for<'a> &'a F: Add<Output = F> + Mul<Output = F>,
+{
+    fn convolve(a: &[Self], b: &[Self]) -> Vec<Self> {
+        if a.len().min(b.len()) < NTT_THRESHOLD {
+            return schoolbook::<Self>(a, b);
+        }
+
+        // The product needs a subgroup with at least as many elements as coefficients
+        let n = (a.len() + b.len() - 1).next_power_of_two();
+        let log_n = n.trailing_zeros();
+        if log_n > Self::TWO_ADICITY {
+            return schoolbook::<Self>(a, b);
+        }
+        ntt_mul(a, b, Self::root_of_unity(log_n), n)
+    }
+}
+
+#[test]
+fn ntt_test() {
+    use crate::field::Gf;
+    use crate::goldilocks::Goldilocks;
+    use num_traits::Pow;
+    type F = crate::F;
+
+    // Transform round trips, and evaluates over the powers of the root
+    let root = F::root_of_unity(3);
+    let coeffs: Vec<F> = (1..=8).map(|i| F::from(i * i)).collect();
+    let mut evals = coeffs.clone();
+    ntt(&mut evals, root);
+    let poly = Polynomial::from(coeffs.iter().rev().copied());
+    for (i, e) in evals.iter().enumerate() {
+        assert_eq!(*e, poly.solve(root.pow(i as u32)));
+    }
+    intt(&mut evals, root);
+    assert_eq!(evals, coeffs);
+
+    // Subgroup interpolation agrees with lagrange
+    let points: Vec<(F, F)> = (0..8).map(|i| (root.pow(i as u32), coeffs[i])).collect();
+    assert_eq!(
+        interpolate_subgroup(&coeffs, root),
+        crate::polynomial::lagrange::<F>(&points)
+    );
+
+    // Coset evaluation matches solving at each point, including past the coset size
+    let offset = F::from(5);
+    for size in [4, 8, 16] {
+        let evals = poly.evaluate_over_coset(offset, size);
+        let w = F::root_of_unity(size.trailing_zeros());
+        for (i, e) in evals.iter().enumerate() {
+            assert_eq!(*e, poly.solve(offset * w.pow(i as u32)));
+        }
+    }
+
+    // NTT multiplication matches schoolbook
+    let a: Vec<F> = (0..100u32)
+        .map(|i| F::from(i.wrapping_mul(2654435761)))
+        .collect();
+    let b: Vec<F> = (0..77u32)
+        .map(|i| F::from(i.wrapping_mul(40503) + 7))
+        .collect();
+    assert_eq!(F::convolve(&a, &b), schoolbook::<F>(&a, &b));
+
+    // Fields without a big enough subgroup fall back to schoolbook
+    type G = Gf<4391>;
+    let a: Vec<G> = (0..40).map(|i| G::from(i + 3)).collect();
+    assert_eq!(G::convolve(&a, &a), schoolbook::<G>(&a, &a));
+
+    // And over the 64 bit field
+    type H = Goldilocks;
+    let a: Vec<H> = (0..100u64).map(|i| H::from(i << 40 | i)).collect();
+    let b: Vec<H> = (0..77).map(|i| H::from(i * 40503 + 7)).collect();
+    assert_eq!(H::convolve(&a, &b), schoolbook::<H>(&a, &b));
+}
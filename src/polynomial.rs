@@ -20,16 +20,48 @@ where
         .iter()
         .enumerate()
         .rev()
-        .find_map(|(degree, coeffs)| (*coeffs != T::zero()).then(|| degree))
+        .find_map(|(degree, coeffs)| (*coeffs != T::zero()).then_some(degree))
         .map(|v| v + 1)
         .unwrap_or(0);
     v.truncate(l);
-    return v;
+    v
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Polynomial<T>(Box<[T]>);
 
+/// Coefficient types that know how to multiply two polynomials, as low to high coefficients.
+/// Prime fields with a big enough power of two subgroup use an NTT (see ntt.rs), anything else
+/// can fall back to schoolbook multiplication.
+pub trait Convolve: Sized {
+    fn convolve(a: &[Self], b: &[Self]) -> Vec<Self>;
+}
+
+impl Convolve for i32 {
+    fn convolve(a: &[Self], b: &[Self]) -> Vec<Self> {
+        schoolbook::<Self>(a, b)
+    }
+}
+
+/// O(n²) multiplication of two non-empty coefficient lists
+pub fn schoolbook<T>(a: &[T], b: &[T]) -> Vec<T>
+where
+    for<'a> &'a T: Mul<Output = T> + Add<Output = T>,
+    T: Zero + Clone,
+{
+    // Allocate space for new poly
+    let mut poly = vec![T::zero(); a.len() + b.len() - 1];
+
+    // Perform multiplication
+    for (degree0, coeff0) in a.iter().enumerate() {
+        for (degree1, coeff1) in b.iter().enumerate() {
+            poly[degree0 + degree1] = &poly[degree0 + degree1] + &(coeff0 * coeff1);
+        }
+    }
+
+    poly
+}
+
 impl<T, II> From<II> for Polynomial<T>
 where
     II: IntoIterator<Item = T>,
@@ -58,12 +90,40 @@ where
 
 impl<T> Polynomial<T> {
     pub fn degree(&self) -> Option<usize> {
-        if self.0.len() == 0 {
+        if self.0.is_empty() {
             None
         } else {
             Some(self.0.len() - 1)
         }
     }
+
+    /// Coefficients, from the constant term up
+    pub fn coeffs(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> Polynomial<T>
+where
+    T: Zero + PartialEq,
+{
+    /// Constructs a polynomial from its coefficients, from the constant term up
+    pub fn from_coeffs(coeffs: Vec<T>) -> Self {
+        Polynomial(reduce(coeffs).into_boxed_slice())
+    }
+}
+
+impl<T> Polynomial<T>
+where
+    for<'a> &'a T: Mul<Output = T>,
+    T: Zero + PartialEq + From<u32>,
+{
+    pub fn derivative(&self) -> Self {
+        let coeffs = (1..self.0.len())
+            .map(|degree| &self.0[degree] * &T::from(degree as u32))
+            .collect();
+        Polynomial::from_coeffs(coeffs)
+    }
 }
 
 impl<T> Polynomial<T>
@@ -228,8 +288,7 @@ where
 
 impl<T> Mul for &Polynomial<T>
 where
-    for<'a> &'a T: Mul<Output = T> + Add<Output = T>,
-    T: Zero + Clone,
+    T: Convolve,
 {
     type Output = Polynomial<T>;
     fn mul(self, rhs: Self) -> Self::Output {
@@ -237,30 +296,18 @@ where
         let degree1 = rhs.degree();
 
         // If either degree is None, return "None"
-        let (degree0, degree1) = match (degree0, degree1) {
-            (Some(d0), Some(d1)) => (d0, d1),
-            (_, _) => return Polynomial(Box::new([])),
-        };
-
-        // Allocate space for new poly
-        let new_degree = degree0 + degree1;
-        let mut poly = vec![T::zero(); new_degree + 1];
-
-        // Perform multiplication
-        for (degree0, coeff0) in self.0.iter().enumerate() {
-            for (degree1, coeff1) in rhs.0.iter().enumerate() {
-                poly[degree0 + degree1] = &poly[degree0 + degree1] + &(coeff0 * coeff1);
-            }
+        if degree0.is_none() || degree1.is_none() {
+            return Polynomial(Box::new([]));
         }
 
-        Polynomial(poly.into_boxed_slice())
+        // Perform multiplication
+        Polynomial(T::convolve(&self.0, &rhs.0).into_boxed_slice())
     }
 }
 
 impl<T> Mul for Polynomial<T>
 where
-    for<'a> &'a T: Mul<Output = T> + Add<Output = T>,
-    T: Zero + Clone,
+    T: Convolve,
 {
     type Output = Self;
     fn mul(self, rhs: Self) -> Self::Output {
@@ -268,23 +315,12 @@ where
         let degree1 = rhs.degree();
 
         // If either degree is None, return "None"
-        let (degree0, degree1) = match (degree0, degree1) {
-            (Some(d0), Some(d1)) => (d0, d1),
-            (_, _) => return Polynomial(Box::new([])),
-        };
-
-        // Allocate space for new poly
-        let new_degree = degree0 + degree1;
-        let mut poly = vec![T::zero(); new_degree + 1];
-
-        // Perform multiplication
-        for (degree0, coeff0) in self.0.iter().enumerate() {
-            for (degree1, coeff1) in rhs.0.iter().enumerate() {
-                poly[degree0 + degree1] = &poly[degree0 + degree1] + &(coeff0 * coeff1);
-            }
+        if degree0.is_none() || degree1.is_none() {
+            return Polynomial(Box::new([]));
         }
 
-        Polynomial(poly.into_boxed_slice())
+        // Perform multiplication
+        Polynomial(T::convolve(&self.0, &rhs.0).into_boxed_slice())
     }
 }
 
@@ -304,33 +340,34 @@ where
     for<'a> &'a T: Sub<Output = T> + Mul<Output = T> + Div<Output = T> + Add<Output = T>,
     T: Zero + PartialEq + Clone,
 {
+    #[allow(clippy::should_implement_trait)]
     pub fn div(lhs: Self, rhs: Self) -> (Self, Self) {
         // Get degree of each poly
         let lhs_degree = lhs.degree().unwrap_or(0);
         let rhs_degree = rhs.degree().unwrap_or(0);
 
         // Return early if division is undoable
-        if lhs_degree < rhs_degree {
+        if lhs.degree().is_none() || lhs_degree < rhs_degree {
             return (Polynomial::from([]), lhs);
         }
 
-        // Get leading coeff
-        let lhs_lead = &lhs[0];
+        // Long division, eliminating the leading term of the remainder one degree at a time
         let rhs_lead = &rhs[0];
-
-        // Construct division poly
-        let diff = lhs_degree - rhs_degree;
-        let mut div = Polynomial::from(vec![T::zero(); diff + 1]);
-        div[0] = lhs_lead / rhs_lead;
-
-        // Calculate remainder
-        let r = lhs - &div * &rhs;
-
-        // Reapply division on remainder
-        let (q, r) = Polynomial::<T>::div(r, rhs);
+        let mut r = lhs.0.into_vec();
+        let mut q = vec![T::zero(); lhs_degree - rhs_degree + 1];
+        for i in (0..q.len()).rev() {
+            q[i] = &r[i + rhs_degree] / rhs_lead;
+            for (j, coeff) in rhs.0.iter().enumerate() {
+                r[i + j] = &r[i + j] - &(&q[i] * coeff);
+            }
+        }
+        r.truncate(rhs_degree);
 
         // Return
-        return (div + q, r);
+        (
+            Polynomial(reduce(q).into_boxed_slice()),
+            Polynomial(reduce(r).into_boxed_slice()),
+        )
     }
 }
 
@@ -344,7 +381,8 @@ where
         + Mul<T, Output = T>
         + Sub<T, Output = T>
         + Pow<u32, Output = T>
-        + PartialEq,
+        + PartialEq
+        + Convolve,
     for<'a> &'a T: Mul<Output = T> + Add<Output = T> + Pow<u32, Output = T>,
 {
     // Generate non-normalized basis polynomials
@@ -382,21 +420,120 @@ where
         .unwrap()
 }
 
+/// Product tree of (x - x_i) over a set of points, each node holds the product of its leaves
+struct SubproductTree<T> {
+    poly: Polynomial<T>,
+    len: usize,
+    children: Option<Box<(SubproductTree<T>, SubproductTree<T>)>>,
+}
+
+impl<T> SubproductTree<T>
+where
+    for<'a> &'a T: Sub<Output = T> + Mul<Output = T> + Div<Output = T> + Add<Output = T>,
+    T: Zero + One + Neg<Output = T> + PartialEq + Clone + Convolve,
+{
+    fn new(xs: &[T]) -> Self {
+        if xs.len() == 1 {
+            return Self {
+                poly: Polynomial::from([T::one(), -xs[0].clone()]),
+                len: 1,
+                children: None,
+            };
+        }
+        let (l, r) = xs.split_at(xs.len() / 2);
+        let (l, r) = (Self::new(l), Self::new(r));
+        Self {
+            poly: &l.poly * &r.poly,
+            len: xs.len(),
+            children: Some(Box::new((l, r))),
+        }
+    }
+
+    /// Evaluates f at every point, reducing f mod each node on the way down
+    fn evaluate(&self, f: Polynomial<T>, out: &mut Vec<T>) {
+        let (_, r) = Polynomial::<T>::div(f, self.poly.clone());
+        match &self.children {
+            None => out.push(r.0.first().cloned().unwrap_or(T::zero())),
+            Some(children) => {
+                children.0.evaluate(r.clone(), out);
+                children.1.evaluate(r, out);
+            }
+        }
+    }
+
+    /// Sum of c_i * M(x) / (x - x_i), where M is the product of the whole tree
+    fn combine(&self, c: &[T]) -> Polynomial<T> {
+        match &self.children {
+            None => Polynomial::from_coeffs(vec![c[0].clone()]),
+            Some(children) => {
+                let (l, r) = c.split_at(children.0.len);
+                children.0.combine(l) * children.1.poly.clone()
+                    + children.1.combine(r) * children.0.poly.clone()
+            }
+        }
+    }
+}
+
+/// Interpolates the polynomial through a set of points with distinct x coordinates, using a
+/// subproduct tree. Produces the same polynomial as `lagrange`, in O(n²) rather than O(n³).
+pub fn interpolate<T>(points: &[(T, T)]) -> Polynomial<T>
+where
+    for<'a> &'a T: Sub<Output = T> + Mul<Output = T> + Div<Output = T> + Add<Output = T>,
+    T: Zero + One + Neg<Output = T> + PartialEq + Clone + Convolve + From<u32>,
+{
+    // M(x) = (x - x_0)(x - x_1)...
+    let xs: Vec<T> = points.iter().map(|(x, _)| x.clone()).collect();
+    let tree = SubproductTree::<T>::new(&xs);
+
+    // The lagrange basis polynomial for x_i is M(x) / (x - x_i) / M'(x_i)
+    let mut weights = Vec::with_capacity(points.len());
+    tree.evaluate(tree.poly.derivative(), &mut weights);
+    let c: Vec<T> = std::iter::zip(points, weights)
+        .map(|((_, y), w)| y / &w)
+        .collect();
+
+    tree.combine(&c)
+}
+
 pub fn fri<T>(poly: &Polynomial<T>, b: T) -> Polynomial<T>
 where
     for<'a> &'a T: Mul<Output = T> + Add<Output = T>,
     T: Zero + Clone + PartialEq,
 {
-    // Fri poly is half the size of input
-    let mut out = vec![T::zero(); poly.0.len() / 2].into_boxed_slice();
+    // Fri poly is half the size of input (rounded up, odd coeffs past the end are zero)
+    let mut out = vec![T::zero(); poly.0.len().div_ceil(2)];
 
     // Perform calculation
-    for i in 0..out.len() {
-        out[i] = &poly.0[2 * i] + &(&b * &poly.0[2 * i + 1]);
+    for (i, coeff) in out.iter_mut().enumerate() {
+        *coeff = match poly.0.get(2 * i + 1) {
+            Some(odd) => &poly.0[2 * i] + &(&b * odd),
+            None => poly.0[2 * i].clone(),
+        };
     }
 
     // Sum and return
-    Polynomial(out)
+    Polynomial(reduce(out).into_boxed_slice())
+}
+
+/// Same as `fri`, but folds the evaluations of a polynomial over a domain closed under
+/// negation (domain[i + n/2] == -domain[i]) into evaluations of the folded polynomial over the
+/// squares of the first half of the domain. Takes the inverses of (at least the first half of)
+/// the domain, so a whole layer doesn't need an inversion per point.
+pub fn fri_evals<T>(evals: &[T], domain_inv: &[T], b: T) -> Vec<T>
+where
+    for<'a> &'a T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T>,
+    T: One + Clone,
+{
+    let half = evals.len() / 2;
+    let inv_two = &T::one() / &(&T::one() + &T::one());
+    (0..half)
+        .map(|i| {
+            // g(x²) = (p(x) + p(-x)) / 2, h(x²) = (p(x) - p(-x)) / 2x
+            let g = &(&evals[i] + &evals[i + half]) * &inv_two;
+            let h = &(&(&evals[i] - &evals[i + half]) * &inv_two) * &domain_inv[i];
+            &g + &(&b * &h)
+        })
+        .collect()
 }
 
 #[test]
@@ -423,6 +560,13 @@ fn fri_test() {
     let h_xx = (p0.solve(x) - p0.solve(nx)) / (x * 2);
     let p1_xx = g_xx + 3 * h_xx;
     assert_eq!(p1_xx.residue(), p1.solve(x * x).residue());
+
+    // Folding evaluations matches evaluating the folded poly, over the 4th roots of unity
+    let domain = [F::from(1), F::from(10), F::from(100), F::from(91)];
+    let evals: Vec<F> = domain.iter().map(|&x| p0.solve(x)).collect();
+    let domain_inv: Vec<F> = domain.iter().map(|x| x.inv()).collect();
+    let folded = fri_evals::<F>(&evals, &domain_inv, F::from(3));
+    assert_eq!(folded, vec![p1.solve(F::from(1)), p1.solve(F::from(100))]);
 }
 
 #[test]
@@ -451,6 +595,19 @@ fn lagrange_test() {
     assert_eq!(poly, lagrange::<F>(&vec![p1, p6, p3, p2]));
     assert_eq!(poly, lagrange::<F>(&vec![p3, p2, p1, p0]));
     assert_eq!(poly, lagrange::<F>(&vec![p6, p5, p4, p3]));
+
+    // Assert subproduct tree interpolation agrees
+    assert_eq!(poly, interpolate::<F>(&[p0, p1, p2, p3]));
+    assert_eq!(poly, interpolate::<F>(&[p0, p3, p5, p6]));
+    assert_eq!(poly, interpolate::<F>(&[p1, p6, p3, p2]));
+    assert_eq!(poly, interpolate::<F>(&[p6, p5, p4, p3, p2]));
+
+    // And on something big enough to multiply with an NTT
+    type G = crate::F;
+    let points: Vec<(G, G)> = (0..70u32)
+        .map(|i| (G::from(i * i + 1), G::from(i.wrapping_mul(2654435761))))
+        .collect();
+    assert_eq!(interpolate::<G>(&points), lagrange::<G>(&points));
 }
 
 #[test]
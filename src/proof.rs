@@ -1,155 +1,435 @@
-use crate::merkle::{self, AuthPath, Hash};
-use crate::F;
-use num_traits::Pow;
+use crate::air::Air;
+use crate::channel::Channel;
+use crate::commitment::VectorCommitment;
+use crate::extension::ExtensionOf;
+use crate::field::Field;
+use crate::hasher::{Sha256, StarkHasher};
+use crate::merkle::{Leaf, Merkle};
+use crate::prover::{commit_statement, fri_query_indices, trace_query_indices, BLOWUP_FACTOR};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
 
-pub struct Proof {
-    state: Hash,
-    data: Box<[u8]>,
+/// Reasons a proof can be rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The proof ended before every expected element was read
+    TruncatedProof,
+    /// The proof doesn't start with the proof magic bytes
+    BadMagic,
+    /// The proof is of a format version this build can't read
+    UnsupportedVersion(u32),
+    /// The proof doesn't have the shape the statement calls for, or has trailing data
+    MalformedProof,
+    /// The proof was checked at no queries, which would accept anything that opens
+    NoQueries,
+    /// Trace decommitment doesn't open the trace commitment
+    TraceMerklePath,
+    /// Composition polynomial decommitment doesn't open the CP commitment
+    CpMerklePath,
+    /// Decommitment of FRI layer n doesn't open that layer's commitment
+    FriMerklePath(usize),
+    /// The trace doesn't satisfy the constraints at the query's test point
+    ConstraintMismatch { query: usize },
+    /// FRI layer n isn't the fold of layer n - 1 at some query
+    FriFoldingMismatch(usize),
+    /// The last FRI layer doesn't fold into the committed free term
+    WrongFreeTerm,
 }
 
-impl Proof {
-    pub fn new(state: Hash, data: Box<[u8]>) -> Self {
-        Self { state, data }
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TruncatedProof => write!(f, "proof is truncated"),
+            Self::BadMagic => write!(f, "not a proof"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported proof version {v}"),
+            Self::MalformedProof => write!(f, "proof is malformed"),
+            Self::NoQueries => write!(f, "proof checked at no queries"),
+            Self::TraceMerklePath => write!(f, "bad merkle path for the trace"),
+            Self::CpMerklePath => write!(f, "bad merkle path for the composition polynomial"),
+            Self::FriMerklePath(n) => write!(f, "bad merkle path for FRI layer {n}"),
+            Self::ConstraintMismatch { query } => write!(f, "constraint mismatch at query {query}"),
+            Self::FriFoldingMismatch(n) => write!(f, "FRI folding mismatch at layer {n}"),
+            Self::WrongFreeTerm => write!(f, "wrong FRI free term"),
+        }
     }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Magic bytes every serialized proof starts with
+pub const PROOF_MAGIC: [u8; 4] = *b"ZKST";
+
+/// Version of the proof format this build reads and writes. Stays at 1 until the first
+/// release, the format is free to change until then. After it, every change to the format
+/// bumps it.
+pub const PROOF_VERSION: u32 = 1;
+
+/// Values of a set of leaves, along with the batch opening of them (a multiproof, for a merkle
+/// tree). A leaf is a single element, or a whole row of them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(bound(serialize = "V: Serialize", deserialize = "V: Deserialize<'de>"))]
+pub struct Decommitment<V, C: VectorCommitment = Merkle> {
+    pub values: Vec<V>,
+    pub opening: C::BatchOpening,
+}
+
+/// Random bytes appended to a trace row before it's hashed into a leaf
+pub type Salt = [u8; 16];
 
-    pub fn verify(&self) {
-        use bincode::deserialize_from;
-        let mut reader = std::io::BufReader::new(&self.data[..]);
-
-        // Pull elements out of proof
-        let f_eval_merkle_root: Hash = deserialize_from(&mut reader).unwrap();
-
-        let alpha0: u32 = deserialize_from(&mut reader).unwrap();
-        let alpha1: u32 = deserialize_from(&mut reader).unwrap();
-        let alpha2: u32 = deserialize_from(&mut reader).unwrap();
-        let cp_eval_merkle_root: Hash = deserialize_from(&mut reader).unwrap();
-
-        let mut betas = vec![0];
-        let mut cp_eval_merkle_roots = vec![cp_eval_merkle_root];
-        for _ in 0..10 {
-            let beta: u32 = deserialize_from(&mut reader).unwrap();
-            let fri_eval_merkle_root: Hash = deserialize_from(&mut reader).unwrap();
-            betas.push(beta);
-            cp_eval_merkle_roots.push(fri_eval_merkle_root);
+/// Salt of leaf i, nothing if the leaves aren't salted
+pub(crate) fn salt(salts: &[Salt], i: usize) -> &[u8] {
+    salts.get(i).map_or(&[], |salt| salt.as_slice())
+}
+
+/// A proof over the field F, with challenges from its extension E drawn with the hash H, and
+/// evaluations committed to with C
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "F: Serialize, E: Serialize",
+    deserialize = "F: Deserialize<'de>, E: Deserialize<'de>"
+))]
+pub struct Proof<F, E = F, H: StarkHasher = Sha256, C: VectorCommitment = Merkle<H>> {
+    magic: [u8; 4],
+    version: u32,
+    // The transcript's hash, which nothing in the proof is made of
+    #[serde(skip)]
+    hasher: PhantomData<H>,
+    /// Commitment to the trace evaluations
+    pub trace_root: C::Commitment,
+    /// Commitment to the composition polynomial evaluations (FRI layer 0)
+    pub cp_root: C::Commitment,
+    /// Commitments to FRI layers 1 and up
+    pub fri_roots: Vec<C::Commitment>,
+    /// Free term of the last FRI polynomial
+    pub free_term: E,
+    /// Trace rows of every query's frame
+    pub trace_decommitment: Decommitment<Vec<F>, C>,
+    /// Salt of every decommitted trace row, or none if the trace leaves aren't salted
+    pub trace_salts: Vec<Salt>,
+    /// cp(x) and cp(-x) of every query, for each FRI layer below the last
+    pub fri_decommitments: Vec<Decommitment<E, C>>,
+}
+
+impl<F: Field, E: ExtensionOf<F>, H: StarkHasher, C: VectorCommitment> Proof<F, E, H, C> {
+    pub fn new(
+        trace_root: C::Commitment,
+        cp_root: C::Commitment,
+        fri_roots: Vec<C::Commitment>,
+        free_term: E,
+        trace_decommitment: Decommitment<Vec<F>, C>,
+        trace_salts: Vec<Salt>,
+        fri_decommitments: Vec<Decommitment<E, C>>,
+    ) -> Self {
+        Self {
+            magic: PROOF_MAGIC,
+            version: PROOF_VERSION,
+            hasher: PhantomData,
+            trace_root,
+            cp_root,
+            fri_roots,
+            free_term,
+            trace_decommitment,
+            trace_salts,
+            fri_decommitments,
         }
-        let fri_free_term: u32 = deserialize_from(&mut reader).unwrap();
-
-        let test_point: u32 = deserialize_from(&mut reader).unwrap();
-        let f_x: (u32, AuthPath) = deserialize_from(&mut reader).unwrap();
-        let f_gx: (u32, AuthPath) = deserialize_from(&mut reader).unwrap();
-        let f_ggx: (u32, AuthPath) = deserialize_from(&mut reader).unwrap();
-        let cp0_x: (u32, AuthPath) = deserialize_from(&mut reader).unwrap();
-        let mut fri_layers = vec![];
-        for _ in 0..10 {
-            let fri_layer: (u32, u32, AuthPath, AuthPath) = deserialize_from(&mut reader).unwrap();
-            fri_layers.push(fri_layer);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    /// Reads a proof, rejecting anything that isn't exactly one proof of a known version
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, VerifyError> {
+        // Check the header before trusting the layout of the rest
+        let (magic, version): ([u8; 4], u32) =
+            bincode::deserialize(bytes).map_err(|_| VerifyError::TruncatedProof)?;
+        if magic != PROOF_MAGIC {
+            return Err(VerifyError::BadMagic);
+        }
+        if version != PROOF_VERSION {
+            return Err(VerifyError::UnsupportedVersion(version));
         }
 
-        // Protocol consts
-        let primitive_root = F::generator();
-        let generator_g = primitive_root.pow(3145728);
-        let generator_h = primitive_root.pow(393216);
-        let g: Vec<F> = (0..1024).map(|n| generator_g.pow(n)).collect();
-        let h: Vec<F> = (0..8192).map(|n| generator_h.pow(n)).collect();
-        let f_domain: Vec<F> = h.iter().map(|n| primitive_root * *n).collect();
+        // Then the body
+        bincode::options()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(bytes)
+            .map_err(|e| match *e {
+                bincode::ErrorKind::Io(_) => VerifyError::TruncatedProof,
+                _ => VerifyError::MalformedProof,
+            })
+    }
 
-        ///////////////////
-        // Prove trace
+    pub fn verify<A: Air<F>>(&self, air: &A, num_queries: usize) -> Result<(), VerifyError> {
+        let mut channel = Channel::<H>::new();
+
+        // Protocol consts
+        let trace_size = air.trace_domain_size();
+        let lde_size = trace_size * BLOWUP_FACTOR;
+        let fri_layers = air.composition_degree_bound().trailing_zeros() as usize;
+        let boundary_constraints = air.boundary_constraints();
+        let num_constraints = boundary_constraints.len() + air.num_transition_constraints();
 
-        // Wrap test point to range 0..8176
-        let test_point = test_point as usize % (8192 - 16);
+        // There have to be queries, and few enough to be distinct points of the evaluation domain
+        if num_queries == 0 {
+            return Err(VerifyError::NoQueries);
+        }
+        if num_queries > lde_size {
+            return Err(VerifyError::MalformedProof);
+        }
 
-        // Verify computation
+        // The proof must have one commitment and decommitment per FRI layer, and full rows of
+        // the trace, with a salt each if they're salted at all
+        if self.fri_roots.len() != fri_layers || self.fri_decommitments.len() != fri_layers {
+            return Err(VerifyError::MalformedProof);
+        }
+        let width = air.trace_width();
+        if self
+            .trace_decommitment
+            .values
+            .iter()
+            .any(|row| row.len() != width)
         {
-            let x = f_domain[test_point];
-            let f_x = F::from(f_x.0);
-            let f_gx = F::from(f_gx.0);
-            let f_ggx = F::from(f_ggx.0);
-
-            let p0 = (f_x - 1) / (x - g[0]);
-            let p1 = (f_x - 2338775057) / (x - g[1022]);
-            let p2 = (f_ggx - f_gx.pow(2) - f_x.pow(2))
-                / ((x.pow(1024) - 1) / ((x - g[1021]) * (x - g[1022]) * (x - g[1023])));
-            let cp0 = alpha0 * p0 + alpha1 * p1 + alpha2 * p2;
-
-            //
-            assert_eq!(cp0.residue(), cp0_x.0);
+            return Err(VerifyError::MalformedProof);
+        }
+        let rows = self.trace_decommitment.values.len();
+        if !self.trace_salts.is_empty() && self.trace_salts.len() != rows {
+            return Err(VerifyError::MalformedProof);
         }
 
-        // Verify trace
-        assert_eq!(
-            merkle::compute_root_from_path(f_x.0, test_point, &f_x.1),
-            f_eval_merkle_root
-        );
-        assert_eq!(
-            merkle::compute_root_from_path(f_gx.0, test_point + 8, &f_gx.1),
-            f_eval_merkle_root
-        );
-        assert_eq!(
-            merkle::compute_root_from_path(f_ggx.0, test_point + 16, &f_ggx.1),
-            f_eval_merkle_root
-        );
-        assert_eq!(
-            merkle::compute_root_from_path(cp0_x.0, test_point, &cp0_x.1),
-            cp_eval_merkle_root
-        );
+        // Replay the transcript, committing the statement and the proof elements and deriving
+        // the challenges
+        commit_statement(&mut channel, air, num_queries);
+        channel.commit(self.trace_root);
 
-        ///////////////////
-        // Prove FRI layers
-
-        // Verify computation for first 9 layers
-        for n in 0..9 {
-            // Get cp(x) and cp(-x) for layer n, and cp(x^2) for layer n + 1
-            let (cp0_x, cp0_nx, _, _) = fri_layers[n];
-            let (cp1_xx, _, _, _) = fri_layers[n + 1];
-            let x = f_domain[test_point].pow(2u32.pow(n as u32));
-
-            // NOTE: the tutorial video got this part wrong!!
-            // The numerator of g(x^2) is NOT cp(x) - cp(-x), it is cp(x) + cp(-x)
-            // cp(x) - cp(-x) will yield something closer to h(x^2) and give bad results
-            let g_xx = (F::from(cp0_x) + F::from(cp0_nx)) / F::from(2);
-            let h_xx = (F::from(cp0_x) - F::from(cp0_nx)) / (x * 2);
-            let calc_cp1_xx = g_xx + betas[n + 1] * h_xx;
-            assert_eq!(cp1_xx, calc_cp1_xx.residue());
-        }
+        let alphas: Vec<E> = (0..num_constraints)
+            .map(|_| channel.draw_field_element::<E>())
+            .collect();
+        channel.commit(self.cp_root);
 
-        // Verify computation for layer 9 and free term
-        {
-            let (cp9_x, cp9_nx, _, _) = fri_layers[9];
-            let cp10_xx = fri_free_term;
-            let x = f_domain[test_point].pow(2u32.pow(9 as u32));
-
-            let g_xx = (F::from(cp9_x) + F::from(cp9_nx)) / F::from(2);
-            let h_xx = (F::from(cp9_x) - F::from(cp9_nx)) / (x * 2);
-            let calc_cp10_xx = g_xx + betas[10] * h_xx;
-            assert_eq!(cp10_xx, calc_cp10_xx.residue());
+        let mut betas = vec![E::zero()];
+        let mut cp_eval_merkle_roots = vec![self.cp_root];
+        for &fri_root in &self.fri_roots {
+            betas.push(channel.draw_field_element::<E>());
+            channel.commit(fri_root);
+            cp_eval_merkle_roots.push(fri_root);
         }
+        channel.commit(self.free_term);
+
+        let queries: Vec<usize> = channel.draw_query_indices(num_queries, lde_size);
+
+        // Protocol consts
+        let primitive_root = F::generator();
+        let generator_g = F::root_of_unity(trace_size.trailing_zeros());
+        let generator_h = F::root_of_unity(lde_size.trailing_zeros());
+
+        ///////////////////
+        // Verify decommitments
+
+        // Trace
+        let indices = trace_query_indices(&queries, air.frame_size(), lde_size);
+        let rows: Vec<(Vec<F>, &[u8])> = self
+            .trace_decommitment
+            .values
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (row.clone(), salt(&self.trace_salts, i)))
+            .collect();
+        let f_eval = open::<_, C>(
+            &indices,
+            rows,
+            &self.trace_decommitment.opening,
+            lde_size,
+            &self.trace_root,
+        )
+        .ok_or(VerifyError::TraceMerklePath)?;
+
+        // FRI layers
+        let cp_evals = self
+            .fri_decommitments
+            .iter()
+            .enumerate()
+            .map(|(n, decommitment)| {
+                let size = lde_size >> n;
+                let indices = fri_query_indices(&queries, size);
+                let error = match n {
+                    0 => VerifyError::CpMerklePath,
+                    n => VerifyError::FriMerklePath(n),
+                };
+                let values = decommitment.values.clone();
+                let root = &cp_eval_merkle_roots[n];
+                open::<_, C>(&indices, values, &decommitment.opening, size, root).ok_or(error)
+            })
+            .collect::<Result<Vec<BTreeMap<usize, E>>, _>>()?;
+
+        // Every division below, inverted in one go. Per query: x, x^trace_size - 1, and x - g[row]
+        // for each boundary constraint. Then 2, shared by every query.
+        let xs: Vec<F> = queries
+            .iter()
+            .map(|&test_point| primitive_root * generator_h.pow(test_point as u32))
+            .collect();
+        let boundary_points: Vec<F> = boundary_constraints
+            .iter()
+            .map(|(_, row, _)| generator_g.pow(*row as u32))
+            .collect();
+        let stride = 2 + boundary_points.len();
+        let mut inverses: Vec<F> = xs
+            .iter()
+            .flat_map(|&x| {
+                let zerofier = x.pow(trace_size as u32) - F::one();
+                [x, zerofier]
+                    .into_iter()
+                    .chain(boundary_points.iter().map(move |&point| x - point))
+            })
+            .collect();
+        inverses.push(F::from(2u32));
+        F::batch_inverse(&mut inverses);
+        let inv_two = E::from(inverses[inverses.len() - 1]);
+
+        for (i, (&test_point, &x)) in std::iter::zip(&queries, &xs).enumerate() {
+            let inv = &inverses[i * stride..(i + 1) * stride];
+            let (inv_x, inv_zerofier, inv_boundary) = (inv[0], inv[1], &inv[2..]);
+
+            ///////////////////
+            // Prove trace
+
+            // Verify computation
+            {
+                let frame: Vec<Vec<F>> = (0..air.frame_size())
+                    .map(|k| {
+                        f_eval[&((test_point + k * BLOWUP_FACTOR) % lde_size)]
+                            .0
+                            .clone()
+                    })
+                    .collect();
+                let mut constraints = vec![];
+
+                // (f(x) - a[row]) / (x - g[row]), with f the constrained column
+                for ((column, _, value), &inv) in
+                    std::iter::zip(&boundary_constraints, inv_boundary)
+                {
+                    constraints.push((frame[0][*column] - *value) * inv);
+                }
 
-        // Verify auth paths for FRI layers
-        for n in 0..10 {
-            // Get data for FRI layer n
-            let size = 8192 >> n;
-            let (cp0_x, cp0_nx, cp0_x_auth_path, cp0_nx_auth_path) = &fri_layers[n];
-            let merkle_root = cp_eval_merkle_roots[n];
-
-            //
-            assert_eq!(
-                merkle::compute_root_from_path(*cp0_x, test_point % size, cp0_x_auth_path),
-                merkle_root,
-            );
-            assert_eq!(
-                merkle::compute_root_from_path(
-                    *cp0_nx,
-                    (test_point + size / 2) % size,
-                    cp0_nx_auth_path
-                ),
-                merkle_root,
-            );
+                // C(f(x), f(gx), ...) * (x - g[e0]) * (x - g[e1]) * ... / (x^trace_size - 1)
+                let inv_denominator = air.transition_exemptions().fold(inv_zerofier, |acc, row| {
+                    acc * (x - generator_g.pow(row as u32))
+                });
+                for numerator in air.transition_constraints(&frame) {
+                    constraints.push(numerator * inv_denominator);
+                }
+
+                let cp0 = std::iter::zip(&alphas, constraints)
+                    .map(|(&alpha, c)| alpha * E::from(c))
+                    .reduce(|acc, c| acc + c)
+                    .unwrap();
+
+                //
+                if cp0 != cp_evals[0][&test_point] {
+                    return Err(VerifyError::ConstraintMismatch { query: test_point });
+                }
+            }
+
+            ///////////////////
+            // Prove FRI layers
+
+            // Verify computation for each layer, the last layer folds into the free term. Layer n
+            // is queried at x^(2^n), so its inverse is squared along with it.
+            let mut inv_x = E::from(inv_x);
+            for n in 0..fri_layers {
+                // Get cp(x) and cp(-x) for layer n, and cp(x^2) for layer n + 1
+                let size = lde_size >> n;
+                let cp0_x = cp_evals[n][&(test_point % size)];
+                let cp0_nx = cp_evals[n][&((test_point + size / 2) % size)];
+                let cp1_xx = match cp_evals.get(n + 1) {
+                    Some(cp_eval) => cp_eval[&(test_point % (size / 2))],
+                    None => self.free_term,
+                };
+
+                // NOTE: the tutorial video got this part wrong!!
+                // The numerator of g(x^2) is NOT cp(x) - cp(-x), it is cp(x) + cp(-x)
+                // cp(x) - cp(-x) will yield something closer to h(x^2) and give bad results
+                let g_xx = (cp0_x + cp0_nx) * inv_two;
+                let h_xx = (cp0_x - cp0_nx) * inv_two * inv_x;
+                let calc_cp1_xx = g_xx + betas[n + 1] * h_xx;
+                inv_x *= inv_x;
+                if cp1_xx != calc_cp1_xx {
+                    return Err(if n + 1 == fri_layers {
+                        VerifyError::WrongFreeTerm
+                    } else {
+                        VerifyError::FriFoldingMismatch(n + 1)
+                    });
+                }
+            }
         }
+
+        Ok(())
     }
 
     pub fn size(&self) -> usize {
-        use std::mem::size_of;
-        size_of::<Self>() + self.data.len()
+        self.to_bytes().len()
+    }
+}
+
+/// Checks the values of the leaves at `indices` against a commitment, returning them by leaf
+/// index
+fn open<V: Leaf, C: VectorCommitment>(
+    indices: &[usize],
+    values: Vec<V>,
+    opening: &C::BatchOpening,
+    size: usize,
+    commitment: &C::Commitment,
+) -> Option<BTreeMap<usize, V>> {
+    if indices.len() != values.len() {
+        return None;
     }
+    let elements: Vec<(usize, V)> = std::iter::zip(indices.iter().copied(), values).collect();
+    C::verify_batch_opening(commitment, size, &elements, opening)
+        .then(|| elements.into_iter().collect())
+}
+
+#[test]
+fn proof_bytes_test() {
+    use crate::fibonacci::FibonacciSq;
+    use crate::hasher::Sha256;
+    use crate::prover::generate_proof;
+    use crate::{E, F};
+
+    let a = FibonacciSq::trace(31, F::from(3141592));
+    let air = FibonacciSq::new(31, a[30]);
+    let proof =
+        generate_proof::<F, E, Sha256, _>(&air, std::slice::from_ref(&a), 4, Channel::new());
+
+    // Round trip
+    let bytes = proof.to_bytes();
+    let read = Proof::<F, E, Sha256>::from_bytes(&bytes).unwrap();
+    assert_eq!(read, proof);
+    assert_eq!(read.verify(&air, 4), Ok(()));
+
+    // Unknown version, not a proof, truncated, trailing data
+    let mut other = bytes.clone();
+    other[4..8].copy_from_slice(&(PROOF_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        Proof::<F, E, Sha256>::from_bytes(&other),
+        Err(VerifyError::UnsupportedVersion(PROOF_VERSION + 1))
+    );
+    other[0] = b'X';
+    assert_eq!(
+        Proof::<F, E, Sha256>::from_bytes(&other),
+        Err(VerifyError::BadMagic)
+    );
+    let truncated = &bytes[..bytes.len() - 1];
+    assert_eq!(
+        Proof::<F, E, Sha256>::from_bytes(truncated),
+        Err(VerifyError::TruncatedProof)
+    );
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(
+        Proof::<F, E, Sha256>::from_bytes(&trailing),
+        Err(VerifyError::MalformedProof)
+    );
 }
@@ -1,12 +1,71 @@
+use crate::air::Air;
 use crate::channel::Channel;
+use crate::commitment::VectorCommitment;
+use crate::extension::ExtensionOf;
+use crate::field::Field;
+use crate::hasher::StarkHasher;
 use crate::merkle::Merkle;
-use crate::polynomial::{fri, lagrange, x, Polynomial};
-use crate::proof::Proof;
-use crate::F;
+use crate::ntt::interpolate_subgroup;
+use crate::polynomial::{fri, fri_evals, interpolate, x, Polynomial};
+use crate::proof::{salt, Decommitment, Proof, Salt};
 use num_traits::Pow;
-use num_traits::{One, Zero};
+use std::collections::BTreeSet;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// Blowup of the evaluation domain over the trace domain
+pub const BLOWUP_FACTOR: usize = 8;
+
+/// Proves the trace, given as its columns, satisfies the AIR. The trace is over F, challenges
+/// and everything derived from them (composition polynomial, FRI layers) are over the
+/// extension E.
+pub fn generate_proof<F: Field, E: ExtensionOf<F>, H: StarkHasher, A: Air<F>>(
+    air: &A,
+    trace: &[Vec<F>],
+    num_queries: usize,
+    channel: Channel<H>,
+) -> Proof<F, E, H>
+where
+    for<'a> &'a F: Add<Output = F> + Sub<Output = F> + Mul<Output = F> + Div<Output = F>,
+    for<'a> &'a F: Pow<u32, Output = F>,
+    for<'a> &'a E: Add<Output = E> + Sub<Output = E> + Mul<Output = E> + Div<Output = E>,
+    for<'a> &'a E: Pow<u32, Output = E>,
+{
+    prove::<F, E, H, Merkle<H>, A>(air, trace, num_queries, channel, false)
+}
+
+/// Same as `generate_proof`, but every trace leaf is salted with fresh randomness, so leaves
+/// that aren't opened can't be brute forced back into trace values. The salts of the opened
+/// leaves go in the proof.
+pub fn generate_salted_proof<F: Field, E: ExtensionOf<F>, H: StarkHasher, A: Air<F>>(
+    air: &A,
+    trace: &[Vec<F>],
+    num_queries: usize,
+    channel: Channel<H>,
+) -> Proof<F, E, H>
+where
+    for<'a> &'a F: Add<Output = F> + Sub<Output = F> + Mul<Output = F> + Div<Output = F>,
+    for<'a> &'a F: Pow<u32, Output = F>,
+    for<'a> &'a E: Add<Output = E> + Sub<Output = E> + Mul<Output = E> + Div<Output = E>,
+    for<'a> &'a E: Pow<u32, Output = E>,
+{
+    prove::<F, E, H, Merkle<H>, A>(air, trace, num_queries, channel, true)
+}
 
-pub fn generate_proof(mut channel: Channel) -> Proof {
+/// Same as `generate_proof`, or `generate_salted_proof` if `salted`, but committing to the
+/// evaluations with any vector commitment C
+pub fn prove<F: Field, E: ExtensionOf<F>, H: StarkHasher, C: VectorCommitment, A: Air<F>>(
+    air: &A,
+    trace: &[Vec<F>],
+    num_queries: usize,
+    mut channel: Channel<H>,
+    salted: bool,
+) -> Proof<F, E, H, C>
+where
+    for<'a> &'a F: Add<Output = F> + Sub<Output = F> + Mul<Output = F> + Div<Output = F>,
+    for<'a> &'a F: Pow<u32, Output = F>,
+    for<'a> &'a E: Add<Output = E> + Sub<Output = E> + Mul<Output = E> + Div<Output = E>,
+    for<'a> &'a E: Pow<u32, Output = E>,
+{
     // I'll do my best to explain things, at least how I understand them thus far.
     //
     // The proof is divided into 4 parts:
@@ -21,65 +80,84 @@ pub fn generate_proof(mut channel: Channel) -> Proof {
     // showing that it is both a low degree poly, and evaluates correctly (at least at a few
     // test points).
 
+    // Everything the verifier checks the proof against goes in the transcript first, so the
+    // challenges are tied to this statement and can't be reused for another
+    commit_statement(&mut channel, air, num_queries);
+
     ///////////////////
     // Part 1:
-    //   In this part, we generate a trace sequence, a lagrange polynomial for that sequence,
-    // and then evaluate said polynomial over an extended domain. I won't go too far into
+    //   In this part, we take the trace sequence, generate a lagrange polynomial for that
+    // sequence, and then evaluate said polynomial over an extended domain. I won't go too far into
     // this part, I found it relatively straight forward from the video guide this project
-    // is based on.
-
-    // Generate the trace sequence of 1023 elements.
-    let mut a = [F::zero(); 1023];
-    a[0] = F::one();
-    a[1] = F::from(3141592); // The secret
-    for i in 2..1023 {
-        let t0 = a[i - 2].pow(2);
-        let t1 = a[i - 1].pow(2);
-        a[i] = t0 + t1;
-    }
-
-    // Assert the trace is correct at the last value
-    assert_eq!(a[1022].residue(), 2338775057);
-
-    // Generate a primitive root of F_3221225473 (this ends up being 5 in the python codebase)
+    // is based on. Every column of the trace gets its own polynomial, but they're all committed
+    // together, one merkle leaf per row.
+
+    // Sizes of the trace domain (g) and the extended evaluation domain (h)
+    let n = air.trace_domain_size();
+    let lde_size = n * BLOWUP_FACTOR;
+    assert_eq!(trace.len(), air.trace_width());
+    assert!(trace
+        .iter()
+        .all(|column| column.len() == air.trace_length()));
+
+    // Generate a primitive root of F (this ends up being 5 in the python codebase)
     let primitive_root = F::generator();
 
-    // Create generators for cyclic groups of sizes 1024 and 8192
-    let generator_g = primitive_root.pow(3145728);
-    let generator_h = primitive_root.pow(393216);
-
-    // Assert generators are of the correct order
-    assert_eq!(generator_g.order(), 1024);
-    assert_eq!(generator_h.order(), 8192);
+    // Roots of unity generating the cyclic groups of sizes n and lde_size
+    let generator_g = F::root_of_unity(n.trailing_zeros());
+    let generator_h = F::root_of_unity(lde_size.trailing_zeros());
 
     // Generate respective cyclic groups
-    let g: Vec<F> = (0..1024).map(|n| generator_g.pow(n)).collect();
-    let h: Vec<F> = (0..8192).map(|n| generator_h.pow(n)).collect();
-
-    // Generate lagrange polynomial going through points (g[i], a[i]) for i <= 1022
-    let points: Vec<(F, F)> = std::iter::zip(&g, &a).map(|(&x, &y)| (x, y)).collect();
-    let f_poly = lagrange::<F>(&points);
-
-    // Assert that the polynomial has the correct solutions at each domain input
-    for (x, y) in std::iter::zip(&g, &a) {
-        assert_eq!(f_poly.solve(*x), *y);
-    }
-
-    // Solve polynomial over h, shifted by the primitive root
+    let g: Vec<F> = (0..n as u32).map(|n| generator_g.pow(n)).collect();
+    let h: Vec<F> = (0..lde_size as u32).map(|n| generator_h.pow(n)).collect();
+
+    // Generate lagrange polynomials going through points (g[i], column[i]). A column filling the
+    // whole subgroup interpolates with an inverse NTT, otherwise use a subproduct tree.
+    let f_polys: Vec<Polynomial<F>> = trace
+        .iter()
+        .map(|column| {
+            let f_poly = if column.len() == n {
+                interpolate_subgroup(column, generator_g)
+            } else {
+                let points: Vec<(F, F)> =
+                    std::iter::zip(&g, column).map(|(&x, &y)| (x, y)).collect();
+                interpolate::<F>(&points)
+            };
+
+            // Assert that the polynomial has the correct solutions at each domain input
+            assert_eq!(
+                &f_poly.evaluate_over_coset(F::one(), n)[..column.len()],
+                column
+            );
+            f_poly
+        })
+        .collect();
+
+    // Solve polynomials over h, shifted by the primitive root, and gather them into rows
     let f_domain: Vec<F> = h.iter().map(|n| primitive_root * *n).collect();
-    let f_eval: Vec<F> = f_domain.iter().map(|&n| f_poly.solve(n)).collect();
-
-    // Assert a few elements of eval are correct
-    assert_eq!(f_eval[0].residue(), 576067152);
-    assert_eq!(f_eval[1].residue(), 3100214617);
-    assert_eq!(f_eval[2].residue(), 2091264768);
-    assert_eq!(f_eval[8189].residue(), 800520420);
-    assert_eq!(f_eval[8190].residue(), 1199720174);
-    assert_eq!(f_eval[8191].residue(), 1076821037);
-
-    // Generate merkle tree from f_eval
-    let f_eval_merkle = Merkle::new(8192, f_eval.iter().map(|f| f.residue()));
-    let f_eval_merkle_root = f_eval_merkle[0];
+    let f_evals: Vec<Vec<F>> = f_polys
+        .iter()
+        .map(|f_poly| f_poly.evaluate_over_coset(primitive_root, lde_size))
+        .collect();
+    let f_eval: Vec<Vec<F>> = (0..lde_size)
+        .map(|i| f_evals.iter().map(|column| column[i]).collect())
+        .collect();
+
+    // Generate merkle tree from f_eval, one leaf per row. A row is a handful of 32 bit values,
+    // few enough to find by trying them all against the leaf hash, so salting appends random
+    // bytes to each one.
+    let trace_salts: Vec<Salt> = match salted {
+        true => (0..lde_size).map(|_| random_salt()).collect(),
+        false => vec![],
+    };
+    let f_eval_merkle = C::commit(
+        lde_size,
+        f_eval
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (row, salt(&trace_salts, i))),
+    );
+    let f_eval_merkle_root = f_eval_merkle.commitment();
 
     // Commit f_eval merkle root
     channel.commit(f_eval_merkle_root);
@@ -90,133 +168,132 @@ pub fn generate_proof(mut channel: Channel) -> Proof {
     // mathematically tie each element of the group together, and are low degree if
     // and only if the math that created the sequence was correct.
 
-    // Constraint 0:
-    // f(x) - a[0]
-    // -----------
-    //  x - g[0]
-    // So, f(x) is a degree 1022, and at g[0] is evaluates to a[0], by definition
-    // (we used lagrange precisely for this property). Therefor, f(x) - a[0] = 0
-    // at g[0]. Therefor, g[0] is a root, and the first constraint divides evenly to
-    // produce a polynomial of degree 1021.
-    let numerator = &f_poly - &x(a[0], 0);
-    let denominator = Polynomial::from([F::one(), -g[0]]);
-    let (c0, c0r) = Polynomial::<F>::div(numerator, denominator);
-
-    // Constraint 1:
-    // f(x) - a[1022]
-    // --------------
-    //  x - g[1022]
-    // Following the logic from constraint 0, f(x) at g[1022] evaluates to a[1022].
-    // Once again, we can use this fact to produce a degree 1021 polynomial.
-    let numerator = &f_poly - &x(a[1022], 0);
-    let denominator = Polynomial::from([F::one(), -g[1022]]);
-    let (c1, c1r) = Polynomial::<F>::div(numerator, denominator);
-
-    // Constraint 2:
-    //              f(g^2 x) - f(g x)^2 - f(x)^2
-    // ------------------------------------------------------
-    // (x^1024 - 1)/(x - g[1021])/(x - g[1022])/(x - g[1023])
-    // We have constraints for our publically known trace values (0, 1) and (1022, 2338775057),
-    // but a constraint for the other 1021 values is a bit more involved. The idea is the same,
-    // but with a twist. Notice g[n] * g == g[n + 1]. In particular, f(g[n] * g) evaluates
-    // the poly at g[n + 1]. We can multiply the input by g to "slide it forward". Our original
-    // trace equation was a[n + 2] = a[n + 1]^2 + a[n]^2. Because f(g[n]) == a[n], we can
-    // transform the equation to f(g[n + 2]) = f(g[n + 1])^2 + f(g[n])^2, and using our sliding
-    // rule we get f(g*g*g[n]) = f(g*g[n]) + f(g[n])^2. Move some terms to the side and we get
-    // f(g*g*x) - f(g*x) - f(x)^2 = 0 where x <- g[n] for each n. Just like the other 2
-    // contraints, we know that this equation has roots = { g[n] | 0 < n < 1022 }. Thus, we can
-    // divide by (x - g[1])(x - g[2])(x - g[3])...(x - g[1021]) to produce a polynomial of
-    // degree 1023.
+    // Boundary constraints:
+    // f(x) - a[row]
+    // -------------
+    //  x - g[row]
+    // for the f of the constrained column
+    // So, f(x) is of degree trace_length - 1, and at g[row] is evaluates to a[row], by definition
+    // (we used lagrange precisely for this property). Therefor, f(x) - a[row] = 0 at g[row].
+    // Therefor, g[row] is a root, and the constraint divides evenly to produce a polynomial
+    // one degree lower.
+    let mut constraints = vec![];
+    for (column, row, value) in air.boundary_constraints() {
+        let numerator = &f_polys[column] - &x(value, 0);
+        let denominator = Polynomial::from([F::one(), -g[row]]);
+        let (c, cr) = Polynomial::<F>::div(numerator, denominator);
+        assert_eq!(cr.degree(), None);
+        constraints.push(c);
+    }
+
+    // Transition constraints:
+    //         C(f(x), f(gx), f(g²x), ...)
+    // ------------------------------------------
+    // (x^n - 1)/(x - g[e0])/(x - g[e1])/...
+    // The boundary constraints pin down our publically known trace values, but a constraint
+    // for all the other values is a bit more involved. The idea is the same, but with a twist.
+    // Notice g[n] * g == g[n + 1]. In particular, f(g[n] * g) evaluates the poly at g[n + 1].
+    // We can multiply the input by g to "slide it forward". For FibonacciSq, the trace
+    // equation was a[n + 2] = a[n + 1]^2 + a[n]^2. Because f(g[n]) == a[n], we can transform
+    // the equation to f(g[n + 2]) = f(g[n + 1])^2 + f(g[n])^2, and using our sliding rule we
+    // get f(g*g*g[n]) = f(g*g[n])^2 + f(g[n])^2. Move some terms to the side and we get
+    // f(g*g*x) - f(g*x)^2 - f(x)^2 = 0 where x <- g[n] for each n where the frame fits in the
+    // trace. Just like the boundary contraints, we know that this equation has those g[n]
+    // as roots, so we can divide by (x - g[0])(x - g[1])...
     //
-    // Note, the denom is not (x - g[1])(x - g[2])... like I said. This multiplcation is very
-    // expensive. Instead, start with precomputed (x^1024 - 1) and divide out the unwanted
+    // Note, the denom is not built as (x - g[0])(x - g[1])... like I said. This multiplcation
+    // is very expensive. Instead, start with precomputed (x^n - 1) and divide out the exempt
     // terms instead.
-    let t0 = f_poly.clone().apply_const(g[2]);
-    let t1 = f_poly.clone().apply_const(g[1]);
-    let t1 = &t1 * &t1;
-    let t2 = &f_poly * &f_poly;
-    let numerator = t0 - t1 - t2;
-
-    let denominator = x(F::one(), 1024) - x(F::one(), 0);
-    let tp0 = Polynomial::from([F::one(), -g[1021]]);
-    let tp1 = Polynomial::from([F::one(), -g[1022]]);
-    let tp2 = Polynomial::from([F::one(), -g[1023]]);
-    let (denominator, t2r) = Polynomial::<F>::div(denominator, &tp2 * &tp0 * tp1);
-    let (c2, c2r) = Polynomial::<F>::div(numerator, denominator);
-
-    // Assert constraints have no remainders
-    assert_eq!(c0r.degree(), None);
-    assert_eq!(c1r.degree(), None);
-    assert_eq!(t2r.degree(), None);
-    assert_eq!(c2r.degree(), None);
-
-    // Assert constraints resolve correctly
-    assert_eq!(c0.degree(), Some(1021));
-    assert_eq!(c1.degree(), Some(1021));
-    assert_eq!(c2.degree(), Some(1023));
-    assert_eq!(c0.solve(F::from(2718)).residue(), 2509888982);
-    assert_eq!(c1.solve(F::from(5772)).residue(), 232961446);
-    assert_eq!(c2.solve(F::from(31415)).residue(), 2090051528);
-
-    // Generate composition polynomial, with a degree of at most 1023 (this is true because poly
-    // addition can't produce higher power terms)
-    let a0 = F::from(channel.get_u32());
-    let a1 = F::from(channel.get_u32());
-    let a2 = F::from(channel.get_u32());
-    let cp_poly = c0 * x(a0, 0) + c1 * x(a1, 0) + c2 * x(a2, 0);
+    let frame: Vec<Vec<Polynomial<F>>> = (0..air.frame_size())
+        .map(|k| {
+            f_polys
+                .iter()
+                .map(|f_poly| f_poly.clone().apply_const(generator_g.pow(k as u32)))
+                .collect()
+        })
+        .collect();
+
+    let denominator = x(F::one(), n) - x(F::one(), 0);
+    let exemptions = air
+        .transition_exemptions()
+        .map(|row| Polynomial::from([F::one(), -g[row]]))
+        .fold(x(F::one(), 0), |acc, p| &acc * &p);
+    let (denominator, tr) = Polynomial::<F>::div(denominator, exemptions);
+    assert_eq!(tr.degree(), None);
+
+    for numerator in air.transition_constraints(&frame) {
+        let (c, cr) = Polynomial::<F>::div(numerator, denominator.clone());
+        assert_eq!(cr.degree(), None);
+        constraints.push(c);
+    }
+
+    // Generate composition polynomial, a random linear combination of all the constraints
+    // (the degree can't grow past the highest constraint, poly addition can't produce higher
+    // power terms). The coefficients are drawn from the extension, so the sum lives there too.
+    let cp_poly = constraints
+        .into_iter()
+        .map(|c| {
+            let c = Polynomial::from_coeffs(c.coeffs().iter().map(|&c| E::from(c)).collect());
+            c * x(channel.draw_field_element::<E>(), 0)
+        })
+        .reduce(|acc, c| acc + c)
+        .unwrap();
 
     // Assert composition polynomial resolves correctly
-    assert_eq!(cp_poly.degree(), Some(1023));
+    let degree_bound = air.composition_degree_bound();
+    assert!(cp_poly.degree().unwrap() < degree_bound);
 
     // Evaluate cp over f_domain
-    let cp_domain = f_domain;
-    let cp_eval: Vec<F> = cp_domain.iter().map(|&n| cp_poly.solve(n)).collect();
+    let mut domain_inv = f_domain;
+    F::batch_inverse(&mut domain_inv);
+    let cp_eval = cp_poly.evaluate_over_coset(E::from(primitive_root), lde_size);
 
     // Generate merkle tree from cp_eval
-    let cp_eval_merkle = Merkle::new(8192, cp_eval.iter().map(|f| f.residue()));
-    let cp_eval_merkle_root = cp_eval_merkle[0];
+    let cp_eval_merkle = C::commit(lde_size, cp_eval.iter().copied());
+    let cp_eval_merkle_root = cp_eval_merkle.commitment();
 
     // Commit cp_eval merkle root
     channel.commit(cp_eval_merkle_root);
 
     ///////////////////
     // Part 3:
-    //   Instead of proving that composite polynomial from earlier is degree 1023, we're
-    // going to prove that it is "close". The video series doesn't go too into depth on
+    //   Instead of proving that composite polynomial from earlier is below the degree bound,
+    // we're going to prove that it is "close". The video series doesn't go too into depth on
     // how this work, this is mostly just going through the motions. Basically, we perform
     // an iterative process of our composite polynomial that cuts the degree in half.
-    // We know the degree is probably 1023, so after 10 iterations, we should have a polynomial
-    // that is degree 0 (just a constant). If it isn't, then our constraint polynomial roots
-    // didn't cancel out well, and the computation wasn't faithful. At least how I understand it.
+    // The degree bound is a power of two, so after log2(bound) iterations, we should have a
+    // polynomial that is degree 0 (just a constant). If it isn't, then our constraint polynomial
+    // roots didn't cancel out well, and the computation wasn't faithful. At least how I
+    // understand it.
+
+    let fri_layers = degree_bound.trailing_zeros() as usize;
+    let mut cp_polys: Vec<Polynomial<E>> = vec![cp_poly];
+    let mut cp_evals: Vec<Vec<E>> = vec![cp_eval];
+    let mut cp_eval_merkles: Vec<C> = vec![cp_eval_merkle];
 
-    let mut cp_polys: Vec<Polynomial<F>> = vec![cp_poly];
-    let mut cp_domains: Vec<Vec<F>> = vec![cp_domain];
-    let mut cp_evals: Vec<Vec<F>> = vec![cp_eval];
-    let mut cp_eval_merkles: Vec<Merkle> = vec![cp_eval_merkle];
+    // Inverses of the current layer's domain, inverted all at once above. The next domain is the
+    // squares of the first half, and so are its inverses.
+    let mut domain_inv: Vec<E> = domain_inv.into_iter().map(E::from).collect();
 
     // Perform FRI operation
-    for _ in 0..10 {
+    for _ in 0..fri_layers {
         // Get new fri poly
-        let beta = F::from(channel.get_u32());
-        let fri_poly = fri::<F>(cp_polys.last().unwrap(), beta);
-
-        // Get new fri domain
-        let mut fri_domain = cp_domains.last().unwrap().clone();
-        fri_domain.truncate(fri_domain.len() / 2);
-        for e in &mut fri_domain {
-            *e = e.pow(2);
+        let beta = channel.draw_field_element::<E>();
+        let fri_poly = fri::<E>(cp_polys.last().unwrap(), beta);
+
+        // Fold the previous layer's evaluations over the new domain
+        let fri_eval = fri_evals::<E>(cp_evals.last().unwrap(), &domain_inv, beta);
+        domain_inv.truncate(domain_inv.len() / 2);
+        for e in &mut domain_inv {
+            *e *= *e;
         }
 
-        // Solve over new domain
-        let fri_eval: Vec<_> = fri_domain.iter().map(|&n| fri_poly.solve(n)).collect();
-
         // Generate merkle tree from fri_eval
-        let fri_eval_merkle = Merkle::new(fri_eval.len(), fri_eval.iter().map(|f| f.residue()));
-        let fri_eval_merkle_root = fri_eval_merkle[0];
+        let fri_eval_merkle = C::commit(fri_eval.len(), fri_eval.iter().copied());
+        let fri_eval_merkle_root = fri_eval_merkle.commitment();
 
         // Push
         cp_polys.push(fri_poly);
-        cp_domains.push(fri_domain);
         cp_evals.push(fri_eval);
         cp_eval_merkles.push(fri_eval_merkle);
 
@@ -224,70 +301,266 @@ pub fn generate_proof(mut channel: Channel) -> Proof {
         channel.commit(fri_eval_merkle_root);
     }
 
-    // Assert the degree of the FRI polynomials
-    assert_eq!(cp_polys[0].degree(), Some(1023));
-    assert_eq!(cp_polys[1].degree(), Some(511));
-    assert_eq!(cp_polys[2].degree(), Some(255));
-    assert_eq!(cp_polys[3].degree(), Some(127));
-    assert_eq!(cp_polys[4].degree(), Some(63));
-    assert_eq!(cp_polys[5].degree(), Some(31));
-    assert_eq!(cp_polys[6].degree(), Some(15));
-    assert_eq!(cp_polys[7].degree(), Some(7));
-    assert_eq!(cp_polys[8].degree(), Some(3));
-    assert_eq!(cp_polys[9].degree(), Some(1));
-    assert_eq!(cp_polys[10].degree(), Some(0));
-
-    // Assert fri_eval sizes
-    assert_eq!(cp_evals[0].len(), 8192);
-    assert_eq!(cp_evals[1].len(), 4096);
-    assert_eq!(cp_evals[2].len(), 2048);
-    assert_eq!(cp_evals[3].len(), 1024);
-    assert_eq!(cp_evals[4].len(), 512);
-    assert_eq!(cp_evals[5].len(), 256);
-    assert_eq!(cp_evals[6].len(), 128);
-    assert_eq!(cp_evals[7].len(), 64);
-    assert_eq!(cp_evals[8].len(), 32);
-    assert_eq!(cp_evals[9].len(), 16);
-    assert_eq!(cp_evals[10].len(), 8);
+    // Assert the degree of the FRI polynomials halves each layer
+    for (i, poly) in cp_polys.iter().enumerate() {
+        assert!(poly.degree().unwrap_or(0) < degree_bound >> i);
+        assert_eq!(cp_evals[i].len(), lde_size >> i);
+    }
 
     // Commit free term of the final polynomial
-    channel.commit(cp_polys[10][0].residue());
+    let free_term = cp_polys[fri_layers].solve(E::zero());
+    assert!(cp_evals[fri_layers].iter().all(|&e| e == free_term));
+    channel.commit(free_term);
 
     ///////////////////
     // Part 4
     //   This is the "decommit" phase. The above 3 steps generate all the data we need, now
-    // we just receive a test point and evaluate that point through each stage, showing that
-    // the math between each stage follows. Again, this was mostly just going through the motions.
-
-    // Get test point
-    let x = channel.get_u32() as usize % (8192 - 16);
-
-    // Decommit on trace
-    let f_x = f_eval[x].residue();
-    let f_x_auth_path = f_eval_merkle.trace(x);
-    let f_gx = f_eval[x + 8].residue();
-    let f_gx_auth_path = f_eval_merkle.trace(x + 8);
-    let f_ggx = f_eval[x + 16].residue();
-    let f_ggx_auth_path = f_eval_merkle.trace(x + 16);
-    let cp0_x = cp_evals[0][x].residue();
-    let cp0_x_auth_path = cp_eval_merkles[0].trace(x);
-    channel.commit((f_x, f_x_auth_path));
-    channel.commit((f_gx, f_gx_auth_path));
-    channel.commit((f_ggx, f_ggx_auth_path));
-    channel.commit((cp0_x, cp0_x_auth_path));
-
-    // Decommit on FRI
-    for i in 0..10 {
-        let len = cp_domains[i].len();
-        let x = x % len;
-        let nx = (x + len / 2) % len;
-        let cp_x = cp_evals[i][x].residue();
-        let cp_x_auth_path = cp_eval_merkles[i].trace(x);
-        let cp_nx = cp_evals[i][nx].residue();
-        let cp_nx_auth_path = cp_eval_merkles[i].trace(nx);
-        channel.commit((cp_x, cp_nx, cp_x_auth_path, cp_nx_auth_path));
-    }
+    // we just receive some test points and evaluate each point through each stage, showing that
+    // the math between each stage follows. Every query has a small chance of catching a cheating
+    // prover, so the more queries, the more sound the proof. Again, this was mostly just going
+    // through the motions.
+
+    // Get test points
+    let queries: Vec<usize> = channel.draw_query_indices(num_queries, lde_size);
+
+    // Decommit on trace, the rows f(x), f(gx), f(g²x), ... for every query
+    let indices = trace_query_indices(&queries, air.frame_size(), lde_size);
+    let trace_decommitment = Decommitment {
+        values: indices.iter().map(|&i| f_eval[i].clone()).collect(),
+        opening: f_eval_merkle.batch_open(&indices),
+    };
+    let trace_salts = indices
+        .iter()
+        .filter_map(|&i| trace_salts.get(i).copied())
+        .collect();
+
+    // Decommit on FRI, cp(x) and cp(-x) for every query (layer 0 covers cp0(x) as well)
+    let fri_decommitments = (0..fri_layers)
+        .map(|i| {
+            let indices = fri_query_indices(&queries, lde_size >> i);
+            Decommitment {
+                values: indices.iter().map(|&j| cp_evals[i][j]).collect(),
+                opening: cp_eval_merkles[i].batch_open(&indices),
+            }
+        })
+        .collect();
 
     // Done
-    return channel.finalize();
+    Proof::new(
+        f_eval_merkle_root,
+        cp_eval_merkle_root,
+        cp_eval_merkles[1..]
+            .iter()
+            .map(|merkle| merkle.commitment())
+            .collect(),
+        free_term,
+        trace_decommitment,
+        trace_salts,
+        fri_decommitments,
+    )
+}
+
+/// Fresh salt for a trace leaf, from the OS
+fn random_salt() -> Salt {
+    let mut salt = Salt::default();
+    getrandom::fill(&mut salt).expect("no system randomness");
+    salt
+}
+
+/// Commits the statement, the AIR's public values and the proof parameters, as the first
+/// message of the transcript. The prover and verifier both start with it.
+pub(crate) fn commit_statement<F: Field, H: StarkHasher, A: Air<F>>(
+    channel: &mut Channel<H>,
+    air: &A,
+    num_queries: usize,
+) {
+    channel.commit((
+        air.boundary_constraints(),
+        air.trace_length(),
+        air.trace_width(),
+        BLOWUP_FACTOR,
+        num_queries,
+    ));
+}
+
+/// Trace leaves decommitted for a set of queries, the whole frame x, gx, g²x, ... of each query
+/// (g is h^BLOWUP_FACTOR, wrapping around). Sorted, each leaf appears once.
+pub fn trace_query_indices(queries: &[usize], frame_size: usize, lde_size: usize) -> Vec<usize> {
+    let indices: BTreeSet<usize> = queries
+        .iter()
+        .flat_map(|&x| (0..frame_size).map(move |k| (x + k * BLOWUP_FACTOR) % lde_size))
+        .collect();
+    indices.into_iter().collect()
+}
+
+/// FRI layer leaves decommitted for a set of queries, x and -x of each query in a layer of
+/// `size` elements. Sorted, each leaf appears once.
+pub fn fri_query_indices(queries: &[usize], size: usize) -> Vec<usize> {
+    let indices: BTreeSet<usize> = queries
+        .iter()
+        .flat_map(|&x| [x % size, (x + size / 2) % size])
+        .collect();
+    indices.into_iter().collect()
+}
+
+#[test]
+fn multi_column_test() {
+    use crate::air::ConstraintValue;
+    use crate::hasher::Sha256;
+    use crate::proof::VerifyError;
+    use crate::{E, F};
+    use num_traits::One;
+
+    // Fibonacci over two columns, (a, b) -> (b, a + b), with the claim b ends in result
+    struct FibonacciPairs {
+        length: usize,
+        result: F,
+    }
+
+    impl Air<F> for FibonacciPairs {
+        fn trace_length(&self) -> usize {
+            self.length
+        }
+
+        fn trace_width(&self) -> usize {
+            2
+        }
+
+        fn boundary_constraints(&self) -> Vec<(usize, usize, F)> {
+            vec![
+                (0, 0, F::one()),
+                (1, 0, F::one()),
+                (1, self.length - 1, self.result),
+            ]
+        }
+
+        fn frame_size(&self) -> usize {
+            2
+        }
+
+        fn num_transition_constraints(&self) -> usize {
+            2
+        }
+
+        fn transition_degree(&self) -> usize {
+            1
+        }
+
+        fn transition_constraints<T: ConstraintValue>(&self, frame: &[Vec<T>]) -> Vec<T> {
+            let (a, b) = (frame[0][0].clone(), frame[0][1].clone());
+            let (next_a, next_b) = (frame[1][0].clone(), frame[1][1].clone());
+            vec![next_a - b.clone(), next_b - a - b]
+        }
+    }
+
+    let mut trace = vec![vec![F::one()], vec![F::one()]];
+    for i in 1..32 {
+        let (a, b) = (trace[0][i - 1], trace[1][i - 1]);
+        trace[0].push(b);
+        trace[1].push(a + b);
+    }
+    let air = FibonacciPairs {
+        length: 32,
+        result: trace[1][31],
+    };
+
+    // Every decommitted trace leaf is a full row
+    let proof = generate_proof::<F, E, Sha256, _>(&air, &trace, 4, Channel::new());
+    assert!(proof
+        .trace_decommitment
+        .values
+        .iter()
+        .all(|row| row.len() == 2));
+    assert_eq!(proof.verify(&air, 4), Ok(()));
+
+    // Rows can't be swapped for other values, or cut short
+    let mut bad = proof.clone();
+    bad.trace_decommitment.values[0][1] = F::from(7u32);
+    assert_eq!(bad.verify(&air, 4), Err(VerifyError::TraceMerklePath));
+    let mut bad = proof.clone();
+    bad.trace_decommitment.values[0].pop();
+    assert_eq!(bad.verify(&air, 4), Err(VerifyError::MalformedProof));
+
+    // Nor prove a different result
+    let air = FibonacciPairs {
+        length: 32,
+        result: trace[0][31],
+    };
+    assert_eq!(proof.verify(&air, 4), Err(VerifyError::TraceMerklePath));
+
+    // A row-local constraint, two columns that are equal on every row, with no exempt rows
+    struct EqualColumns;
+
+    impl Air<F> for EqualColumns {
+        fn trace_length(&self) -> usize {
+            32
+        }
+
+        fn trace_width(&self) -> usize {
+            2
+        }
+
+        fn boundary_constraints(&self) -> Vec<(usize, usize, F)> {
+            vec![(0, 0, F::one())]
+        }
+
+        fn frame_size(&self) -> usize {
+            1
+        }
+
+        fn num_transition_constraints(&self) -> usize {
+            1
+        }
+
+        fn transition_degree(&self) -> usize {
+            1
+        }
+
+        fn transition_constraints<T: ConstraintValue>(&self, frame: &[Vec<T>]) -> Vec<T> {
+            vec![frame[0][0].clone() - frame[0][1].clone()]
+        }
+    }
+
+    assert_eq!(EqualColumns.composition_degree_bound(), 32);
+    let trace = vec![trace[0].clone(), trace[0].clone()];
+    let proof = generate_proof::<F, E, Sha256, _>(&EqualColumns, &trace, 4, Channel::new());
+    assert_eq!(proof.verify(&EqualColumns, 4), Ok(()));
+}
+
+#[test]
+fn salted_proof_test() {
+    use crate::fibonacci::FibonacciSq;
+    use crate::hasher::Sha256;
+    use crate::proof::VerifyError;
+    use crate::{E, F};
+
+    let a = FibonacciSq::trace(31, F::from(3141592));
+    let air = FibonacciSq::new(31, a[30]);
+    let trace = std::slice::from_ref(&a);
+
+    // Unsalted proofs carry no salts, salted ones one per decommitted row
+    let proof = generate_proof::<F, E, Sha256, _>(&air, trace, 4, Channel::new());
+    assert!(proof.trace_salts.is_empty());
+    let salted = generate_salted_proof::<F, E, Sha256, _>(&air, trace, 4, Channel::new());
+    assert_eq!(
+        salted.trace_salts.len(),
+        salted.trace_decommitment.values.len()
+    );
+    assert_eq!(salted.verify(&air, 4), Ok(()));
+
+    // The same trace commits differently every time
+    let again = generate_salted_proof::<F, E, Sha256, _>(&air, trace, 4, Channel::new());
+    assert_ne!(again.trace_root, salted.trace_root);
+    assert_ne!(again.trace_root, proof.trace_root);
+
+    // Salts have to be the committed ones, one per row
+    let mut bad = salted.clone();
+    bad.trace_salts[0][0] ^= 1;
+    assert_eq!(bad.verify(&air, 4), Err(VerifyError::TraceMerklePath));
+    let mut bad = salted.clone();
+    bad.trace_salts.pop();
+    assert_eq!(bad.verify(&air, 4), Err(VerifyError::MalformedProof));
+    let mut bad = salted.clone();
+    bad.trace_salts.clear();
+    assert_eq!(bad.verify(&air, 4), Err(VerifyError::TraceMerklePath));
 }
@@ -0,0 +1,340 @@
+use crate::field::{Field, Gf};
+use crate::hasher::{StarkHasher, NODE_TAG};
+use crate::F;
+use num_traits::{Inv, One, Pow, Zero};
+use sha3::digest::{ExtendableOutput, Update, XofReader};
+use std::sync::OnceLock;
+
+/// Elements in the permutation's state
+pub const WIDTH: usize = 16;
+
+/// Elements of the state that are never absorbed into or squeezed out, 8 * 31.6 bits for F
+pub const CAPACITY: usize = 8;
+
+/// Elements absorbed per permutation, and the length of the output
+pub const RATE: usize = WIDTH - CAPACITY;
+
+const SECURITY_LEVEL: u32 = 128;
+
+/// The Rescue-Prime permutation over Gf<P>. The exponent, round count, MDS matrix and round
+/// constants all come from the parameter generation in the Rescue-Prime specification
+/// (Szepieniec, Ashur, Dhooghe), for (P, WIDTH, CAPACITY, SECURITY_LEVEL).
+pub struct RescuePrime<const P: u32> {
+    alpha: u32,
+    alpha_inv: u32,
+    mds: [[Gf<P>; WIDTH]; WIDTH],
+    // Two rows of WIDTH per round, the first added after the alpha s-box and the second after
+    // the inverse one
+    round_constants: Vec<Gf<P>>,
+}
+
+impl<const P: u32> Default for RescuePrime<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const P: u32> RescuePrime<P> {
+    pub fn new() -> Self {
+        // Smallest alpha for which x^alpha is a permutation, and the exponent undoing it
+        let alpha = (3..).find(|&a| gcd(a, P - 1) == 1).unwrap();
+        let alpha_inv = inverse_mod(alpha, P - 1);
+        let rounds = num_rounds(alpha);
+
+        // Constants are read from SHAKE256 of the parameters, a few bytes more than P per
+        // constant so reducing them is close to uniform
+        let mut shake = sha3::Shake256::default();
+        shake.update(format!("Rescue-XLIX({P},{WIDTH},{CAPACITY},{SECURITY_LEVEL})").as_bytes());
+        let mut reader = shake.finalize_xof();
+        let bytes_per_int = (32 - P.leading_zeros()).div_ceil(8) as usize + 1;
+        let round_constants = (0..2 * WIDTH * rounds)
+            .map(|_| {
+                let mut bytes = [0; 8];
+                reader.read(&mut bytes[..bytes_per_int]);
+                Gf::from((u64::from_le_bytes(bytes) % P as u64) as u32)
+            })
+            .collect();
+
+        // The MDS matrix is the transpose of the right half of the echelon form of the
+        // WIDTH x 2 WIDTH vandermonde matrix of the generator
+        let g = Gf::<P>::generator();
+        let mut v: Vec<Vec<Gf<P>>> = (0..WIDTH)
+            .map(|i| (0..2 * WIDTH).map(|j| g.pow((i * j) as u32)).collect())
+            .collect();
+        for col in 0..WIDTH {
+            let pivot = (col..WIDTH).find(|&row| !v[row][col].is_zero()).unwrap();
+            v.swap(col, pivot);
+            let inv = v[col][col].inv();
+            v[col].iter_mut().for_each(|x| *x *= inv);
+            let pivot_row = v[col].clone();
+            for (i, row) in v.iter_mut().enumerate() {
+                let factor = row[col];
+                if i != col && !factor.is_zero() {
+                    for (x, &y) in row.iter_mut().zip(&pivot_row) {
+                        *x = *x - factor * y;
+                    }
+                }
+            }
+        }
+        let mds = std::array::from_fn(|i| std::array::from_fn(|j| v[j][WIDTH + i]));
+
+        RescuePrime {
+            alpha,
+            alpha_inv,
+            mds,
+            round_constants,
+        }
+    }
+
+    pub fn permute(&self, state: &mut [Gf<P>; WIDTH]) {
+        for round in self.round_constants.chunks(2 * WIDTH) {
+            let (first, second) = round.split_at(WIDTH);
+            for (exp, constants) in [(self.alpha, first), (self.alpha_inv, second)] {
+                // S-box, then mix and add the round constants
+                let sbox = state.map(|x| x.pow(exp));
+                for ((s, row), &c) in state.iter_mut().zip(&self.mds).zip(constants) {
+                    *s = row.iter().zip(&sbox).fold(c, |acc, (&m, &x)| acc + m * x);
+                }
+            }
+        }
+    }
+
+    /// Sponge hash of a sequence of field elements, to RATE elements
+    pub fn hash(&self, input: &[Gf<P>]) -> [Gf<P>; RATE] {
+        self.hash_in_domain(Gf::zero(), input)
+    }
+
+    /// Like `hash`, but with the first capacity element starting at `domain` rather than zero.
+    /// Hashes in different domains are independent, whatever their inputs.
+    pub fn hash_in_domain(&self, domain: Gf<P>, input: &[Gf<P>]) -> [Gf<P>; RATE] {
+        // Pad with a one and then zeros to a whole number of chunks, so no two inputs collide
+        let mut padded = input.to_vec();
+        padded.push(Gf::one());
+        padded.resize(padded.len().next_multiple_of(RATE), Gf::zero());
+
+        let mut state = [Gf::zero(); WIDTH];
+        state[RATE] = domain;
+        for chunk in padded.chunks(RATE) {
+            for (s, &x) in state.iter_mut().zip(chunk) {
+                *s = *s + x;
+            }
+            self.permute(&mut state);
+        }
+        state[..RATE].try_into().unwrap()
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// a^-1 mod n, by the extended euclidean algorithm
+fn inverse_mod(a: u32, n: u32) -> u32 {
+    let (mut r0, mut r1) = (n as i64, a as i64);
+    let (mut t0, mut t1) = (0i64, 1i64);
+    while r1 != 0 {
+        let q = r0 / r1;
+        (r0, r1) = (r1, r0 - q * r1);
+        (t0, t1) = (t1, t0 - q * t1);
+    }
+    t0.rem_euclid(n as i64) as u32
+}
+
+/// Rounds needed against the Groebner basis attack on the sponge, with a 50% margin on top
+fn num_rounds(alpha: u32) -> usize {
+    let (m, rate) = (WIDTH as u64, RATE as u64);
+    let l1 = (1..)
+        .find(|&n| {
+            let dcon = (alpha as u64 - 1) * m * (n - 1) / 2 + 2;
+            let v = m * (n - 1) + rate;
+            // The attack costs (v + dcon choose v)^2
+            binomial_exceeds(v + dcon, v, SECURITY_LEVEL / 2)
+        })
+        .unwrap();
+    (3 * l1.max(5) as usize).div_ceil(2)
+}
+
+/// Whether n choose k is larger than 2^bits, for bits below 64
+fn binomial_exceeds(n: u64, k: u64, bits: u32) -> bool {
+    // Each step is the exact binomial (n - k + i choose i), stopping before it can overflow
+    let k = k.min(n - k);
+    let mut c = 1u128;
+    for i in 1..=k {
+        c = c * (n - k + i) as u128 / i as u128;
+        if c > 1 << bits {
+            return true;
+        }
+    }
+    false
+}
+
+/// Rescue-Prime over F as a merkle and transcript hash. Nodes are merged as field elements, so
+/// an authentication path is checked with field arithmetic alone, which keeps verifying it
+/// inside a circuit cheap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rescue;
+
+impl Rescue {
+    fn permutation() -> &'static RescuePrime<{ F::MODULUS }> {
+        static PERMUTATION: OnceLock<RescuePrime<{ F::MODULUS }>> = OnceLock::new();
+        PERMUTATION.get_or_init(RescuePrime::new)
+    }
+
+    fn to_digest(elements: [F; RATE]) -> [u8; 32] {
+        let bytes: Vec<u8> = elements
+            .iter()
+            .flat_map(|x| x.residue().to_be_bytes())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    /// The residues of a digest, None if any of them isn't below P. Reducing those instead
+    /// would let two different digests stand for the same node.
+    fn from_digest(digest: &[u8; 32]) -> Option<Vec<F>> {
+        digest
+            .chunks(4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+            .map(|x| (x < F::MODULUS).then(|| F::from(x)))
+            .collect()
+    }
+}
+
+/// A digest whose residues are all past P, what any node with a non-canonical child merges to
+const NON_CANONICAL: [u8; 32] = [0xff; 32];
+
+impl StarkHasher for Rescue {
+    // RATE canonical residues, big endian
+    type Digest = [u8; 32];
+
+    fn hash(data: &[u8]) -> Self::Digest {
+        // The length, then three bytes per element, which always fits below P
+        let elements: Vec<F> = std::iter::once(F::from(data.len() as u32))
+            .chain(
+                data.chunks(3)
+                    .map(|chunk| F::from(chunk.iter().fold(0, |acc, &b| acc << 8 | b as u32))),
+            )
+            .collect();
+        Self::to_digest(Self::permutation().hash(&elements))
+    }
+
+    fn merge(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+        // A non-canonical digest is never the output of a hash, so neither is its parent. Merge
+        // to one that isn't either, which no root or cap can match.
+        let (Some(left), Some(right)) = (Self::from_digest(left), Self::from_digest(right)) else {
+            return NON_CANONICAL;
+        };
+
+        // Nodes are kept apart from leaves by hashing in their own domain, not by a prefix
+        let elements = [left, right].concat();
+        let domain = F::from(NODE_TAG as u32);
+        Self::to_digest(Self::permutation().hash_in_domain(domain, &elements))
+    }
+
+    fn challenge_bytes(digest: &Self::Digest) -> Vec<u8> {
+        // Residues aren't uniform over all 32 bits, but P - 1 = 3 * 2^30 so the bits below the
+        // two-adicity are, up to a single extra zero
+        const BYTES: usize = (F::TWO_ADICITY / 8) as usize;
+        digest
+            .chunks(4)
+            .flat_map(|b| b[4 - BYTES..].to_vec())
+            .collect()
+    }
+}
+
+#[test]
+fn rescue_test() {
+    use crate::channel::Channel;
+    use crate::merkle::{compute_root_from_multi_path, compute_root_from_path, Merkle};
+    use crate::E;
+
+    fn hex(digest: [u8; 32]) -> String {
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    // Parameters and vectors from an independent implementation of the specification's
+    // reference code for p = 3221225473, m = 16, capacity = 8, 128 bit security
+    let rescue = Rescue::permutation();
+    assert_eq!((rescue.alpha, rescue.alpha_inv), (5, 1288490189));
+    assert_eq!(rescue.round_constants.len(), 2 * WIDTH * 8);
+    assert_eq!(rescue.round_constants[0], F::from(930853113));
+    assert_eq!(rescue.round_constants[255], F::from(1410922014));
+    assert_eq!(rescue.mds[0][0], F::from(2945006262u32));
+    assert_eq!(rescue.mds[15][15], F::from(2302638606u32));
+
+    let mut state = std::array::from_fn(|i| F::from(i as u32));
+    rescue.permute(&mut state);
+    let expected: [u32; WIDTH] = [
+        673882166, 2605191902, 1658867449, 1114745296, 2238208427, 1941620417, 2135592014,
+        1920189200, 2882743300, 919061573, 673450678, 1876324445, 201680679, 673525239, 2948594670,
+        3086765740,
+    ];
+    assert_eq!(state, expected.map(F::from));
+
+    let input: Vec<F> = (1..=8).map(F::from).collect();
+    let expected: [u32; RATE] = [
+        2140116750, 886385018, 2723630166, 965384198, 1021493078, 817814949, 21390588, 32244496,
+    ];
+    assert_eq!(rescue.hash(&input), expected.map(F::from));
+    let expected: [u32; RATE] = [
+        542460560, 1587107598, 1996377870, 479981324, 1276616570, 2828894391, 1450533633,
+        2268224752,
+    ];
+    assert_eq!(rescue.hash(&[]), expected.map(F::from));
+
+    // Bytes and digests hash as field elements
+    let abc = Rescue::hash(b"abc");
+    assert_eq!(
+        hex(abc),
+        "8565d8cb229ac1c2775427df0f0f859575a88ad41be105ebb53a6b5a0a4623a1"
+    );
+    assert_eq!(
+        hex(Rescue::merge(&abc, &Rescue::hash(b""))),
+        "03a013ad8cb3fa6aaac6f82a8e34eafa2fe107f02f7556481be46ea12942bd61"
+    );
+    assert_ne!(Rescue::hash(b"\0abc"), abc);
+
+    // Trees open, one path at a time or several at once
+    let merkle: Merkle<Rescue> = Merkle::new(4, [1u32, 2, 3, 4].into_iter());
+    assert_eq!(
+        compute_root_from_path::<Rescue, _>(&[3u32], 2, 4, &merkle.trace(2)),
+        Some(merkle.root())
+    );
+    let elements = [(0, 1u32), (3, 4u32)];
+    assert_eq!(
+        compute_root_from_multi_path::<Rescue, _>(4, &elements, &merkle.multi_trace(&[0, 3])),
+        Some(merkle.root())
+    );
+
+    // A sibling with a residue swapped for the same one plus P doesn't open the tree
+    let path = merkle.trace(2);
+    let aliased = (0..RATE).find_map(|i| {
+        let chunk = u32::from_be_bytes(path[0][4 * i..4 * i + 4].try_into().unwrap());
+        let mut path = path.clone();
+        let alias = chunk.checked_add(F::MODULUS)?;
+        path[0][4 * i..4 * i + 4].copy_from_slice(&alias.to_be_bytes());
+        Some(path)
+    });
+    let aliased = aliased.unwrap();
+    assert_eq!(Rescue::merge(&aliased[0], &path[1]), NON_CANONICAL);
+    assert_ne!(
+        compute_root_from_path::<Rescue, _>(&[3u32], 2, 4, &aliased),
+        Some(merkle.root())
+    );
+
+    // Transcripts draw from the uniform low bytes of each residue only, deterministically
+    assert_eq!(Rescue::challenge_bytes(&abc).len(), RATE * 3);
+    assert_eq!(Rescue::challenge_bytes(&abc)[..3], abc[1..4]);
+    let mut c0 = Channel::<Rescue>::new();
+    let mut c1 = Channel::<Rescue>::new();
+    c0.commit(7u32);
+    c1.commit(7u32);
+    let e0: E = c0.draw_field_element();
+    assert_eq!(e0, c1.draw_field_element());
+    let indices = c0.draw_query_indices(4, 64);
+    assert_eq!(indices, c1.draw_query_indices(4, 64));
+    assert!(indices.iter().all(|&i| i < 64));
+}